@@ -0,0 +1,95 @@
+use std::io::BufRead;
+use crate::connect::reply;
+use crate::connect::cluster::Cluster;
+use crate::helper::{self, MyHelper};
+use crate::output::Output;
+use crate::pipeline;
+
+/// Sends the commands read from `input` one at a time, printing each reply
+/// before the next command goes out. Data lines follow their command line as
+/// in the REPL, and so do `\` meta-commands. With a `depth` above 1, up to
+/// that many commands go out as one pipeline instead; `pipeline { ... }`
+/// blocks always do. Returns the exit status: 1 if the run stopped at an
+/// error reply, or that of the connection or protocol error that stopped it.
+pub fn run(cluster: &mut Cluster, helper: &MyHelper, input: impl BufRead, output: &mut Output,
+           fail_fast: bool, depth: usize) -> i32 {
+    let mut queue = Vec::new();
+    let mut lines = input.lines();
+    while let Some(line) = lines.next() {
+        let mut line = match line {
+            Ok(line) => line,
+            Err(err) => { eprintln!("ERROR: {}", err); return 1 }
+        };
+        let command = line.trim_end_matches('\r');
+        if command.is_empty() || command.starts_with('#') { continue }
+        if command == "quit" { break }
+        line = command.to_string();
+        /* Queued commands go out first, so `\format` or `\stats` sees them done. */
+        if line.starts_with('\\') {
+            let code = flush(cluster, &mut queue, output, fail_fast);
+            if code != 0 { return code; }
+        }
+        if let Some(res) = crate::meta(&line, cluster, output) {
+            if let Err(err) = res {
+                eprintln!("ERROR: {}", err);
+                if fail_fast { return 1 }
+            }
+            continue;
+        }
+
+        while let Ok(true) = helper.incomplete(&line) {
+            match lines.next() {
+                Some(Ok(data)) => {
+                    line.push('\n');
+                    line.push_str(data.trim_end_matches('\r'));
+                },
+                Some(Err(err)) => { eprintln!("ERROR: {}", err); return 1 }
+                None => break,
+            }
+        }
+
+        let failed = if helper::is_pipeline(&line) {
+            let code = flush(cluster, &mut queue, output, fail_fast);
+            if code != 0 { return code; }
+            match helper.pipeline(&line) {
+                Ok(reqs) => match pipeline::run(cluster, reqs, output) {
+                    Ok(failed) => failed,
+                    Err(code) => return code,
+                },
+                Err(err) => { eprintln!("ERROR: {}", err); true }
+            }
+        } else {
+            match helper.request(&line) {
+                Ok(req) if depth > 1 => {
+                    queue.push(req);
+                    if queue.len() < depth { continue }
+                    let code = flush(cluster, &mut queue, output, fail_fast);
+                    if code != 0 { return code; }
+                    false
+                },
+                Ok(req) => {
+                    let replies = cluster.write(req.clone());
+                    output.print(&req, &replies);
+                    /* A failed transport stops the run whether or not --fail-fast is on. */
+                    if let Some(err) = replies.iter().find_map(|(_, resp)| resp.as_ref().err()) {
+                        return err.exit_code();
+                    }
+                    replies.iter().any(|(_, resp)| resp.as_ref().is_ok_and(|r| reply::is_error(r)))
+                },
+                Err(err) => { eprintln!("ERROR: {}", err); true }
+            }
+        };
+        if failed && fail_fast { return 1 }
+    }
+    return flush(cluster, &mut queue, output, fail_fast);
+}
+
+/* Sends the queued commands as one pipeline; --fail-fast looks at the whole batch. */
+fn flush(cluster: &mut Cluster, queue: &mut Vec<Vec<u8>>, output: &Output, fail_fast: bool) -> i32 {
+    if queue.is_empty() { return 0; }
+    return match pipeline::run(cluster, std::mem::take(queue), output) {
+        Ok(true) if fail_fast => 1,
+        Ok(_) => 0,
+        Err(code) => code,
+    };
+}
@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clap::Args;
+use serde_json::{json, Value};
+use crate::connect::reply;
+use crate::connect::cluster::Cluster;
+use crate::connect::error::Error;
+
+/// Requests the load generator can send.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Op {
+    Get,
+    Set,
+    Mget,
+    LopInsert,
+    LopGet,
+    SopInsert,
+    SopGet,
+    MopInsert,
+    MopGet,
+    BopInsert,
+    BopGet,
+}
+
+const OPS: [(&str, Op); 11] = [
+    ("get", Op::Get), ("set", Op::Set), ("mget", Op::Mget),
+    ("lop-insert", Op::LopInsert), ("lop-get", Op::LopGet),
+    ("sop-insert", Op::SopInsert), ("sop-get", Op::SopGet),
+    ("mop-insert", Op::MopInsert), ("mop-get", Op::MopGet),
+    ("bop-insert", Op::BopInsert), ("bop-get", Op::BopGet),
+];
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(OPS.iter().find(|(_, op)| op == self).map(|(name, _)| *name).unwrap_or("?"))
+    }
+}
+
+/// Weighted request mix, e.g. "get=8,set=2".
+#[derive(Clone, Debug)]
+pub struct Mix(Vec<(Op, u32)>);
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mix, String> {
+        let mut mix = Vec::new();
+        for part in s.split(',') {
+            let (name, weight) = part.split_once('=').unwrap_or((part, "1"));
+            let op = match OPS.iter().find(|(n, _)| *n == name.trim()) {
+                Some((_, op)) => *op,
+                None => return Err(format!("unknown request \"{}\" (one of: {})", name,
+                                           OPS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", "))),
+            };
+            let weight = weight.trim().parse::<u32>().map_err(|_| format!("invalid weight \"{}\"", weight))?;
+            if weight > 0 { mix.push((op, weight)); }
+        }
+        if mix.is_empty() { return Err("empty mix".to_string()); }
+        return Ok(Mix(mix));
+    }
+}
+
+impl fmt::Display for Mix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|(op, w)| format!("{}={}", op, w)).collect();
+        f.write_str(&parts.join(","))
+    }
+}
+
+/// Value sizes: a fixed "N" bytes, or "MIN..MAX" picked uniformly.
+#[derive(Clone, Copy, Debug)]
+pub struct Sizes {
+    min: usize,
+    max: usize,
+}
+
+impl FromStr for Sizes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sizes, String> {
+        let parse = |n: &str| n.trim().parse::<usize>().map_err(|_| format!("invalid size \"{}\"", n));
+        let (min, max) = match s.split_once("..") {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if min > max { return Err(format!("{} is larger than {}", min, max)); }
+        return Ok(Sizes { min, max });
+    }
+}
+
+impl fmt::Display for Sizes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max { write!(f, "{}", self.min) } else { write!(f, "{}..{}", self.min, self.max) }
+    }
+}
+
+/// Options of the bench subcommand.
+#[derive(Args, Debug)]
+pub struct Options {
+    /// Requests to send and their weights: get, set, mget, and insert/get of lop, sop, mop and bop
+    #[arg(long, default_value = "get=8,set=2")]
+    mix: Mix,
+
+    /// Number of distinct keys of each kind
+    #[arg(long, default_value_t = 10000, value_parser = clap::value_parser!(u64).range(1..))]
+    keys: u64,
+
+    /// Prefix of the keys the benchmark uses
+    #[arg(long, default_value = "bench:")]
+    key_prefix: String,
+
+    /// Value size in bytes, fixed (N) or uniformly distributed (MIN..MAX)
+    #[arg(long, default_value = "100")]
+    value_size: Sizes,
+
+    /// Keys per mget request
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    mget_keys: u64,
+
+    /// Maximum element count of each collection
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    elements: u64,
+
+    /// Connections (one thread each) sending requests at the same time
+    #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    connections: u64,
+
+    /// How long to run, in seconds
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    duration: u64,
+
+    /// Also write the results to this file as JSON
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+}
+
+/* xorshift64*; good enough to pick keys and sizes. */
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        return self.0.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Builds the requests of one connection.
+struct Workload<'a> {
+    opts: &'a Options,
+    total: u32,
+    rng: Rng,
+}
+
+impl<'a> Workload<'a> {
+    fn new(opts: &'a Options, seed: u64) -> Workload<'a> {
+        Workload { opts, total: opts.mix.0.iter().map(|(_, w)| w).sum(), rng: Rng::new(seed) }
+    }
+
+    fn op(&mut self) -> Op {
+        let mut pick = self.rng.below(self.total as u64) as u32;
+        for &(op, weight) in self.opts.mix.0.iter() {
+            if pick < weight { return op; }
+            pick -= weight;
+        }
+        return self.opts.mix.0[0].0;
+    }
+
+    fn key(&mut self, kind: &str) -> String {
+        format!("{}{}:{}", self.opts.key_prefix, kind, self.rng.below(self.opts.keys))
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        let Sizes { min, max } = self.opts.value_size;
+        let len = min + self.rng.below((max - min) as u64 + 1) as usize;
+        return (0..len).map(|_| b'a' + self.rng.below(26) as u8).collect();
+    }
+
+    /* A collection insert; it creates the collection if it is not there yet. */
+    fn insert(&mut self, head: String) -> Vec<u8> {
+        let data = self.value();
+        let mut req = format!("{} {} create 0 0 {}\r\n", head, data.len(), self.opts.elements).into_bytes();
+        req.extend_from_slice(&data);
+        req.extend_from_slice(b"\r\n");
+        return req;
+    }
+
+    fn request(&mut self, op: Op) -> Vec<u8> {
+        let elements = self.opts.elements;
+        return match op {
+            Op::Get => format!("get {}\r\n", self.key("kv")).into_bytes(),
+            Op::Set => {
+                let key = self.key("kv");
+                let data = self.value();
+                let mut req = format!("set {} 0 0 {}\r\n", key, data.len()).into_bytes();
+                req.extend_from_slice(&data);
+                req.extend_from_slice(b"\r\n");
+                req
+            },
+            Op::Mget => {
+                let keys: Vec<String> = (0..self.opts.mget_keys).map(|_| self.key("kv")).collect();
+                let keys = keys.join(" ");
+                format!("mget {} {}\r\n{}\r\n", keys.len(), self.opts.mget_keys, keys).into_bytes()
+            },
+            Op::LopInsert => {
+                let key = self.key("lop");
+                self.insert(format!("lop insert {} -1", key))
+            },
+            Op::LopGet => format!("lop get {} 0..{}\r\n", self.key("lop"), elements.min(10) - 1).into_bytes(),
+            Op::SopInsert => {
+                let key = self.key("sop");
+                self.insert(format!("sop insert {}", key))
+            },
+            Op::SopGet => format!("sop get {} {}\r\n", self.key("sop"), elements.min(10)).into_bytes(),
+            Op::MopInsert => {
+                let key = self.key("mop");
+                let field = self.rng.below(elements);
+                self.insert(format!("mop insert {} f{}", key, field))
+            },
+            Op::MopGet => format!("mop get {} 0 0\r\n", self.key("mop")).into_bytes(),
+            Op::BopInsert => {
+                let key = self.key("bop");
+                let bkey = self.rng.below(elements);
+                self.insert(format!("bop insert {} {}", key, bkey))
+            },
+            Op::BopGet => format!("bop get {} 0..{} {}\r\n", self.key("bop"), elements, elements.min(10)).into_bytes(),
+        };
+    }
+}
+
+/// Latencies and outcomes of one kind of request.
+#[derive(Default)]
+struct Stats {
+    /// Latency of every request, in microseconds.
+    latencies: Vec<u64>,
+    /// Requests answered with ERROR, CLIENT_ERROR or SERVER_ERROR.
+    errors: u64,
+    /// Requests that got no reply.
+    failures: u64,
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+        self.failures += other.failures;
+    }
+
+    /* Latency at quantile `q` of the sorted latencies. */
+    fn quantile(&self, q: f64) -> u64 {
+        if self.latencies.is_empty() { return 0; }
+        let at = ((self.latencies.len() as f64 * q).ceil() as usize).clamp(1, self.latencies.len());
+        return self.latencies[at - 1];
+    }
+
+    fn json(&self, secs: f64) -> Value {
+        json!({
+            "requests": self.latencies.len(),
+            "throughput": self.latencies.len() as f64 / secs,
+            "errors": self.errors,
+            "failures": self.failures,
+            "latency_us": {
+                "p50": self.quantile(0.5),
+                "p99": self.quantile(0.99),
+                "p999": self.quantile(0.999),
+                "max": self.latencies.last().copied().unwrap_or(0),
+            },
+        })
+    }
+}
+
+/* Sends requests over one connection until `deadline`. */
+fn work(cluster: &mut Cluster, opts: &Options, seed: u64, deadline: Instant) -> BTreeMap<Op, Stats> {
+    let mut workload = Workload::new(opts, seed);
+    let mut stats: BTreeMap<Op, Stats> = BTreeMap::new();
+    while Instant::now() < deadline {
+        let op = workload.op();
+        let req = workload.request(op);
+        let started = Instant::now();
+        let replies = cluster.write(req);
+        let elapsed = started.elapsed();
+        let stats = stats.entry(op).or_default();
+        stats.latencies.push(elapsed.as_micros() as u64);
+        if replies.iter().any(|(_, resp)| resp.is_err()) {
+            stats.failures += 1;
+        } else if replies.iter().any(|(_, resp)| resp.as_ref().is_ok_and(|r| reply::is_error(r))) {
+            stats.errors += 1;
+        }
+    }
+    return stats;
+}
+
+/// Runs the benchmark over `cluster` and the connections `open` adds, prints
+/// a summary and writes the JSON result file if asked to. Returns the exit status.
+pub fn run(opts: &Options, cluster: Cluster, open: &dyn Fn() -> Result<Cluster, Error>) -> i32 {
+    let mut clusters = vec![cluster];
+    for _ in 1..opts.connections {
+        match open() {
+            Ok(cluster) => clusters.push(cluster),
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                return err.exit_code();
+            }
+        }
+    }
+    eprintln!("bench: {} connection(s) to {} for {}s, mix {}, {} keys, values of {} bytes",
+              opts.connections, clusters[0].name, opts.duration, opts.mix, opts.keys, opts.value_size);
+
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |d| d.as_nanos() as u64);
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(opts.duration);
+    let results: Vec<BTreeMap<Op, Stats>> = thread::scope(|s| {
+        let workers: Vec<_> = clusters.iter_mut().enumerate().map(|(i, cluster)| {
+            s.spawn(move || work(cluster, opts, seed.wrapping_add(i as u64 * 0x9E37_79B9), deadline))
+        }).collect();
+        workers.into_iter().map(|w| w.join().unwrap_or_default()).collect()
+    });
+    let secs = started.elapsed().as_secs_f64();
+
+    let mut per_op: BTreeMap<Op, Stats> = BTreeMap::new();
+    for (op, stats) in results.into_iter().flatten() {
+        per_op.entry(op).or_default().merge(stats);
+    }
+    let mut total = Stats::default();
+    for stats in per_op.values_mut() {
+        stats.latencies.sort_unstable();
+        total.latencies.extend_from_slice(&stats.latencies);
+        total.errors += stats.errors;
+        total.failures += stats.failures;
+    }
+    total.latencies.sort_unstable();
+
+    println!("{:<12} {:>10} {:>12} {:>8} {:>8} {:>10} {:>10} {:>10}",
+             "request", "count", "req/s", "errors", "failed", "p50(us)", "p99(us)", "p999(us)");
+    for (name, stats) in per_op.iter().map(|(op, s)| (op.to_string(), s)).chain([("total".to_string(), &total)]) {
+        println!("{:<12} {:>10} {:>12.0} {:>8} {:>8} {:>10} {:>10} {:>10}",
+                 name, stats.latencies.len(), stats.latencies.len() as f64 / secs, stats.errors, stats.failures,
+                 stats.quantile(0.5), stats.quantile(0.99), stats.quantile(0.999));
+    }
+
+    if let Some(path) = opts.json.as_ref() {
+        let result = json!({
+            "target": clusters[0].name,
+            "connections": opts.connections,
+            "duration_s": secs,
+            "mix": opts.mix.to_string(),
+            "keys": opts.keys,
+            "value_size": opts.value_size.to_string(),
+            "total": total.json(secs),
+            "requests": per_op.iter().map(|(op, s)| (op.to_string(), s.json(secs))).collect::<serde_json::Map<_, _>>(),
+        });
+        if let Err(err) = File::create(path).map_err(serde_json::Error::io)
+                                            .and_then(|file| serde_json::to_writer_pretty(file, &result)) {
+            eprintln!("ERROR: {}: {}", path.display(), err);
+            return 1;
+        }
+    }
+    return 0;
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::connect::{self, reply, Transport};
+use crate::response::{self, first_line, Collection, Response};
+
+/// Why a typed request failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The connection failed and could not be restored, or the reply did not parse.
+    Transport(connect::error::Error),
+    /// The server answered `ERROR`, `CLIENT_ERROR` or `SERVER_ERROR`.
+    Server(String),
+    /// The reply did not have the shape the request calls for.
+    Unexpected(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "{}", err),
+            Error::Server(line) => write!(f, "{}", line),
+            Error::Unexpected(line) => write!(f, "unexpected reply: {}", line),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<connect::error::Error> for Error {
+    fn from(err: connect::error::Error) -> Error {
+        Error::Transport(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A key-value item.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    pub flags: u32,
+    pub data: Vec<u8>,
+    /// Only filled in by `gets`.
+    pub cas: Option<u64>,
+}
+
+/// Attributes for `create` on collection inserts.
+#[derive(Clone, Debug, Default)]
+pub struct Attributes {
+    pub flags: u32,
+    pub exptime: i64,
+    pub maxcount: i64,
+    pub ovflaction: Option<String>,
+    pub unreadable: bool,
+}
+
+impl fmt::Display for Attributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.flags, self.exptime, self.maxcount)?;
+        if let Some(action) = self.ovflaction.as_ref() { write!(f, " {}", action)?; }
+        if self.unreadable { write!(f, " unreadable")?; }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOp { And, Or, Xor }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompOp { EQ, NE, LT, LE, GT, GE }
+
+/// `<fwhere> [<bitwop> <foperand>] <compop> <fvalue>`; operands and values are hex strings.
+#[derive(Clone, Debug)]
+pub struct EflagFilter {
+    pub fwhere: usize,
+    pub bitop: Option<(BitOp, String)>,
+    pub compop: CompOp,
+    pub fvalue: String,
+}
+
+impl EflagFilter {
+    pub fn new(fwhere: usize, compop: CompOp, fvalue: &str) -> EflagFilter {
+        EflagFilter { fwhere, bitop: None, compop, fvalue: fvalue.to_string() }
+    }
+
+    pub fn bitop(mut self, op: BitOp, foperand: &str) -> EflagFilter {
+        self.bitop = Some((op, foperand.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for EflagFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.fwhere)?;
+        if let Some((op, operand)) = self.bitop.as_ref() {
+            let op = match op { BitOp::And => "&", BitOp::Or => "|", BitOp::Xor => "^" };
+            write!(f, " {} {}", op, operand)?;
+        }
+        write!(f, " {:?} {}", self.compop, self.fvalue)
+    }
+}
+
+/// One page of `scan key`.
+#[derive(Clone, Debug, Default)]
+pub struct Scan {
+    /// Where the next page starts; "0" once the scan is complete.
+    pub cursor: String,
+    pub keys: Vec<String>,
+}
+
+fn unexpected(line: &str) -> Error {
+    Error::Unexpected(line.to_string())
+}
+
+fn number<T: std::str::FromStr>(word: Option<&str>, line: &str) -> Result<T> {
+    word.and_then(|w| w.parse().ok()).ok_or_else(|| unexpected(line))
+}
+
+/* A reply that reads, but not as the request calls for. */
+fn unexpected_reply(resp: Response) -> Error {
+    match resp {
+        Response::Status(line) | Response::Error(line) => unexpected(&line),
+        Response::Lines(lines) => unexpected(lines.first().map_or("", String::as_str)),
+        resp => unexpected(&format!("{:?}", resp)),
+    }
+}
+
+/// Typed requests over one connection. Replies come back as values, not text.
+pub struct Client {
+    transport: Transport,
+}
+
+impl Client {
+    /// Wraps a transport; the connection opens with the first request.
+    pub fn new(transport: Transport) -> Client {
+        Client { transport }
+    }
+
+    pub fn transport(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    /// Sends a raw request (command line plus any data) and returns the raw reply.
+    pub fn raw(&mut self, req: Vec<u8>) -> Result<Vec<u8>> {
+        let reply = self.transport.request(req)?;
+        if reply::is_error(&reply) {
+            return Err(Error::Server(first_line(&reply).0));
+        }
+        return Ok(reply);
+    }
+
+    /* Sends `req` and takes its reply apart, as `response::parse` does for the CLI. */
+    fn parse(&mut self, req: Vec<u8>) -> Result<Response> {
+        let reply = self.raw(req.clone())?;
+        return Ok(response::parse(&req, &reply));
+    }
+
+    /* Requests that answer with a single status line, e.g. STORED or NOT_FOUND. */
+    fn status(&mut self, req: Vec<u8>) -> Result<String> {
+        let reply = self.raw(req)?;
+        return Ok(first_line(&reply).0);
+    }
+
+    fn with_data(line: String, data: &[u8]) -> Vec<u8> {
+        let mut req = line.into_bytes();
+        req.extend_from_slice(b"\r\n");
+        req.extend_from_slice(data);
+        req.extend_from_slice(b"\r\n");
+        return req;
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Option<Item>> {
+        let mut items = self.retrieve("get", &[key])?;
+        return Ok(items.remove(key));
+    }
+
+    pub fn gets(&mut self, key: &str) -> Result<Option<Item>> {
+        let mut items = self.retrieve("gets", &[key])?;
+        return Ok(items.remove(key));
+    }
+
+    /// Items for every key found; missing keys are left out.
+    pub fn get_multi(&mut self, keys: &[&str]) -> Result<BTreeMap<String, Item>> {
+        self.retrieve("get", keys)
+    }
+
+    fn retrieve(&mut self, cmd: &str, keys: &[&str]) -> Result<BTreeMap<String, Item>> {
+        return match self.parse(format!("{} {}", cmd, keys.join(" ")).into_bytes())? {
+            Response::Items(items) => Ok(items.into_iter().collect()),
+            resp => Err(unexpected_reply(resp)),
+        };
+    }
+
+    fn store(&mut self, cmd: &str, key: &str, flags: u32, exptime: i64, data: &[u8]) -> Result<String> {
+        let line = format!("{} {} {} {} {}", cmd, key, flags, exptime, data.len());
+        self.status(Client::with_data(line, data))
+    }
+
+    /// Returns the status: `STORED` or `NOT_STORED`.
+    pub fn set(&mut self, key: &str, flags: u32, exptime: i64, data: &[u8]) -> Result<String> {
+        self.store("set", key, flags, exptime, data)
+    }
+
+    pub fn add(&mut self, key: &str, flags: u32, exptime: i64, data: &[u8]) -> Result<String> {
+        self.store("add", key, flags, exptime, data)
+    }
+
+    pub fn replace(&mut self, key: &str, flags: u32, exptime: i64, data: &[u8]) -> Result<String> {
+        self.store("replace", key, flags, exptime, data)
+    }
+
+    pub fn append(&mut self, key: &str, data: &[u8]) -> Result<String> {
+        self.store("append", key, 0, 0, data)
+    }
+
+    pub fn prepend(&mut self, key: &str, data: &[u8]) -> Result<String> {
+        self.store("prepend", key, 0, 0, data)
+    }
+
+    /// Returns the status: `STORED`, `EXISTS` or `NOT_FOUND`.
+    pub fn cas(&mut self, key: &str, flags: u32, exptime: i64, data: &[u8], cas: u64) -> Result<String> {
+        let line = format!("cas {} {} {} {} {}", key, flags, exptime, data.len(), cas);
+        self.status(Client::with_data(line, data))
+    }
+
+    /// Whether the key existed.
+    pub fn delete(&mut self, key: &str) -> Result<bool> {
+        let status = self.status(format!("delete {}", key).into_bytes())?;
+        return match status.as_str() {
+            "DELETED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            _ => Err(unexpected(&status)),
+        };
+    }
+
+    /// The new value, or `None` if the key does not exist.
+    pub fn incr(&mut self, key: &str, delta: u64) -> Result<Option<u64>> {
+        self.arith("incr", key, delta)
+    }
+
+    pub fn decr(&mut self, key: &str, delta: u64) -> Result<Option<u64>> {
+        self.arith("decr", key, delta)
+    }
+
+    fn arith(&mut self, cmd: &str, key: &str, delta: u64) -> Result<Option<u64>> {
+        let status = self.status(format!("{} {} {}", cmd, key, delta).into_bytes())?;
+        if status == "NOT_FOUND" { return Ok(None); }
+        return number(Some(&status), &status).map(Some);
+    }
+
+    /// Returns the status, e.g. `STORED`, `CREATED_STORED`, `NOT_FOUND` or `OVERFLOWED`.
+    pub fn lop_insert(&mut self, key: &str, index: i64, data: &[u8], create: Option<&Attributes>) -> Result<String> {
+        let mut line = format!("lop insert {} {} {}", key, index, data.len());
+        if let Some(attrs) = create { line += &format!(" create {}", attrs); }
+        self.status(Client::with_data(line, data))
+    }
+
+    /// Returns the status, e.g. `STORED`, `CREATED_STORED`, `ELEMENT_EXISTS` or `OVERFLOWED`.
+    pub fn bop_insert(&mut self, key: &str, bkey: &str, eflag: Option<&str>, data: &[u8],
+                      create: Option<&Attributes>) -> Result<String> {
+        let mut line = format!("bop insert {} {}", key, bkey);
+        if let Some(eflag) = eflag { line += &format!(" {}", eflag); }
+        line += &format!(" {}", data.len());
+        if let Some(attrs) = create { line += &format!(" create {}", attrs); }
+        self.status(Client::with_data(line, data))
+    }
+
+    /// Elements in `range` (a bkey or `from..to`), or `None` if the key does not exist.
+    pub fn bop_get(&mut self, key: &str, range: &str, filter: Option<&EflagFilter>,
+                   offset: usize, count: usize) -> Result<Option<Collection>> {
+        let mut line = format!("bop get {} {}", key, range);
+        if let Some(filter) = filter { line += &format!(" {}", filter); }
+        line += &format!(" {} {}", offset, count);
+        return match self.parse(line.into_bytes())? {
+            Response::Collection(collection) => Ok(Some(collection)),
+            Response::Status(status) if status == "NOT_FOUND" => Ok(None),
+            Response::Status(status) if status == "NOT_FOUND_ELEMENT" =>
+                Ok(Some(Collection { status, ..Default::default() })),
+            resp => Err(unexpected_reply(resp)),
+        };
+    }
+
+    /// The item attributes by name, or `None` if the key does not exist.
+    pub fn getattr(&mut self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        return match self.parse(format!("getattr {}", key).into_bytes())? {
+            Response::Attributes(attrs) => Ok(Some(attrs.into_iter().collect())),
+            Response::Status(status) if status == "NOT_FOUND" => Ok(None),
+            resp => Err(unexpected_reply(resp)),
+        };
+    }
+
+    /// One page of keys from `cursor` ("0" to start), optionally by glob pattern and item type.
+    pub fn scan(&mut self, cursor: &str, count: Option<usize>, pattern: Option<&str>,
+                kind: Option<&str>) -> Result<Scan> {
+        let mut line = format!("scan key {}", cursor);
+        if let Some(count) = count { line += &format!(" count {}", count); }
+        if let Some(pattern) = pattern { line += &format!(" match {}", pattern); }
+        if let Some(kind) = kind { line += &format!(" type {}", kind); }
+        let reply = self.raw(line.into_bytes())?;
+
+        let mut lines = reply.split(|&b| b == b'\n')
+                             .map(|l| String::from_utf8_lossy(l).trim_end_matches('\r').to_string());
+        let head = lines.next().unwrap_or_default();
+        let cursor = match head.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["KEYS", _, cursor] => cursor.to_string(),
+            _ => return Err(unexpected(&head)),
+        };
+        let keys = lines.take_while(|l| l != "END")
+                        .filter_map(|l| l.split_whitespace().next().map(str::to_string))
+                        .collect();
+        return Ok(Scan { cursor, keys });
+    }
+
+    /// `stats [<args>]` as name/value pairs, in server order.
+    pub fn stats(&mut self, args: &str) -> Result<Vec<(String, String)>> {
+        return match self.parse(format!("stats {}", args).trim_end().as_bytes().to_vec())? {
+            Response::Stats(stats) => Ok(stats),
+            resp => Err(unexpected_reply(resp)),
+        };
+    }
+
+    pub fn version(&mut self) -> Result<String> {
+        let status = self.status(b"version".to_vec())?;
+        return status.strip_prefix("VERSION ").map(str::to_string).ok_or_else(|| unexpected(&status));
+    }
+}
@@ -0,0 +1,378 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use rustls::ClientConfig;
+use super::credentials::Credentials;
+use super::{merge, reply, Transport};
+use super::error::Error;
+use super::event::Log;
+use super::ketama::Ketama;
+use super::stream::{Reconnect, Timeouts};
+use super::udp::Framing;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Multi {
+    Get,
+    Mget,
+    BopMget,
+    BopSmget,
+}
+
+/// Kind and keys of a request that names several keys.
+fn keys_of(req: &[u8]) -> Option<(Multi, Vec<String>)> {
+    let mut lines = req.split(|&b| b == b'\n').map(String::from_utf8_lossy);
+    let line = lines.next()?;
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (multi, keys) = match words.as_slice() {
+        ["get" | "gets", keys @ ..] => (Multi::Get, keys.iter().map(|k| k.to_string()).collect()),
+        ["mget" | "mgets", ..] => (Multi::Mget, Vec::new()),
+        ["bop", "mget", ..] => (Multi::BopMget, Vec::new()),
+        ["bop", "smget", ..] => (Multi::BopSmget, Vec::new()),
+        _ => return None,
+    };
+    if multi == Multi::Get { return Some((multi, keys)); }
+    let data = lines.next()?;
+    return Some((multi, data.split_whitespace().map(str::to_string).collect()));
+}
+
+/// Rewrites a multi-key request for a subset of its keys.
+fn narrow(req: &[u8], multi: Multi, keys: &[String]) -> Vec<u8> {
+    let line = req.split(|&b| b == b'\n').next().unwrap_or(b"");
+    let line = String::from_utf8_lossy(line);
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let list = keys.join(" ");
+    let head = match multi {
+        Multi::Get => return format!("{} {}\r\n", words[0], list).into_bytes(),
+        Multi::Mget => words[..1].to_vec(),
+        Multi::BopMget | Multi::BopSmget => words[..2].to_vec(),
+    };
+    let rest = words.get(head.len() + 2..).unwrap_or(&[]);
+    let mut line = head.join(" ");
+    line += &format!(" {} {}", list.len(), keys.len());
+    for word in rest {
+        line.push(' ');
+        line.push_str(word);
+    }
+    return format!("{}\r\n{}\r\n", line, list).into_bytes();
+}
+
+/// Key of a request, for the commands that act on a single item.
+fn key_of(req: &[u8]) -> Option<String> {
+    let mut lines = req.split(|&b| b == b'\n').map(String::from_utf8_lossy);
+    let line = lines.next()?;
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let key = match words.as_slice() {
+        ["mget" | "mgets", ..] | ["bop", "mget" | "smget", ..] =>
+            return lines.next()?.split_whitespace().next().map(str::to_string),
+        ["lop" | "sop" | "mop" | "bop", _, key, ..] => key,
+        ["get" | "gets" | "set" | "add" | "replace" | "append" | "prepend" | "cas" |
+         "delete" | "incr" | "decr" | "touch" | "getattr" | "setattr", key, ..] => key,
+        _ => return None,
+    };
+    return Some(key.to_string());
+}
+
+/// Replies to one request, or why there is none, with the node each came from.
+pub type Replies = Vec<(String, Result<Vec<u8>, Error>)>;
+
+/// The cache nodes of one ARCUS cluster. Requests with a key go to the node
+/// that owns it on the ketama ring; the rest go to every node.
+pub struct Cluster {
+    pub name: String,
+    nodes: Vec<Transport>,
+    ring: Ketama,
+}
+
+impl Cluster {
+    pub fn new(name: String, nodes: Vec<Transport>) -> Cluster {
+        let names: Vec<String> = nodes.iter().map(|n| n.name().to_string()).collect();
+        Cluster { name, ring: Ketama::new(&names), nodes }
+    }
+
+    pub fn setting(&mut self, rqid: u16, time: u64, auth: Option<Credentials>) {
+        for node in self.nodes.iter_mut() {
+            node.setting(rqid, time, auth.clone());
+        }
+    }
+
+    pub fn bind(&mut self, addr: SocketAddr) {
+        for node in self.nodes.iter_mut() {
+            node.bind(addr);
+        }
+    }
+
+    pub fn framing(&mut self, framing: Framing) {
+        for node in self.nodes.iter_mut() {
+            node.framing(framing);
+        }
+    }
+
+    /// Detects the UDP framing of every UDP node.
+    pub fn probe(&mut self) -> Vec<(String, io::Result<Framing>)> {
+        self.nodes.iter_mut()
+                  .filter_map(|node| node.probe().map(|res| (node.name().to_string(), res)))
+                  .collect()
+    }
+
+    pub fn retry(&mut self, retry: Reconnect) {
+        for node in self.nodes.iter_mut() {
+            node.retry(retry);
+        }
+    }
+
+    pub fn timeouts(&mut self, timeouts: Timeouts) {
+        for node in self.nodes.iter_mut() {
+            node.timeouts(timeouts);
+        }
+    }
+
+    /// Nodes whose connection is down.
+    pub fn disconnected(&self) -> Vec<&str> {
+        self.nodes.iter().filter(|node| !node.is_connected()).map(|node| node.name()).collect()
+    }
+
+    /// The REPL prompt, flagging nodes that are down.
+    pub fn prompt(&self) -> String {
+        let down = self.disconnected().len();
+        return match down {
+            0 => format!("{}> ", self.name),
+            _ if down == self.nodes.len() => format!("{} (disconnected)> ", self.name),
+            _ => format!("{} ({}/{} down)> ", self.name, down, self.nodes.len()),
+        };
+    }
+
+    pub fn log(&mut self, log: Log) {
+        for node in self.nodes.iter_mut() {
+            node.log(log.clone());
+        }
+    }
+
+    pub fn secure(&mut self, config: Arc<ClientConfig>) {
+        for node in self.nodes.iter_mut() {
+            node.secure(config.clone());
+        }
+    }
+
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Opens every connection that is down, returning the nodes that failed.
+    pub fn connect(&mut self) -> Vec<(String, Error)> {
+        self.nodes.iter_mut()
+                  .filter_map(|node| node.connect().err().map(|err| (node.name().to_string(), err)))
+                  .collect()
+    }
+
+    /// Sends one request and returns each reply, or why there is none, with
+    /// the node it came from. Requests that expect no reply leave no entry.
+    pub fn write(&mut self, buf: Vec<u8>) -> Replies {
+        if self.nodes.len() > 1 {
+            if let Some((multi, keys)) = keys_of(&buf) {
+                return vec![(self.name.clone(), self.scatter(&buf, multi, keys))];
+            }
+        }
+        let targets = match self.owner(&buf) {
+            Some(at) => at..at + 1,
+            None => 0..self.nodes.len(),
+        };
+        return self.nodes[targets].iter_mut()
+                                  .map(|n| (n.name().to_string(), n.request(buf.clone())))
+                                  .filter(|(_, r)| !matches!(r, Ok(reply) if reply.is_empty()))
+                                  .collect();
+    }
+
+    /// Sends requests back-to-back, each node's share in one go, and returns
+    /// the replies of every request in order. Requests for several keys or
+    /// every node go out on their own, after the ones before them.
+    pub fn pipeline(&mut self, reqs: Vec<Vec<u8>>) -> Vec<Replies> {
+        let mut replies: Vec<Replies> = reqs.iter().map(|_| Vec::new()).collect();
+        let mut pending: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (i, req) in reqs.iter().enumerate() {
+            let multi = self.nodes.len() > 1 && keys_of(req).is_some();
+            match self.owner(req) {
+                Some(at) if !multi => pending.entry(at).or_default().push(i),
+                _ => {
+                    self.flush(&reqs, &mut pending, &mut replies);
+                    replies[i] = self.write(req.clone());
+                },
+            }
+        }
+        self.flush(&reqs, &mut pending, &mut replies);
+        return replies;
+    }
+
+    /* Pipelines the pending requests of each node. */
+    fn flush(&mut self, reqs: &[Vec<u8>], pending: &mut BTreeMap<usize, Vec<usize>>,
+             replies: &mut [Replies]) {
+        for (at, ids) in std::mem::take(pending) {
+            let node = &mut self.nodes[at];
+            let res = node.pipeline(ids.iter().map(|&i| reqs[i].clone()).collect());
+            for (i, reply) in ids.into_iter().zip(res) {
+                if !matches!(&reply, Ok(reply) if reply.is_empty()) {
+                    replies[i] = vec![(node.name().to_string(), reply)];
+                }
+            }
+        }
+    }
+
+    /* The node that owns the key of a single-key request, if there is one. */
+    fn owner(&self, buf: &[u8]) -> Option<usize> {
+        return match self.nodes.len() {
+            1 => Some(0),
+            _ => key_of(buf).and_then(|key| self.ring.locate(&key)),
+        };
+    }
+
+    /* Splits a multi-key request by owning node and merges the replies. */
+    fn scatter(&mut self, buf: &[u8], multi: Multi, keys: Vec<String>) -> Result<Vec<u8>, Error> {
+        let mut owned: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for key in keys.iter() {
+            let at = self.ring.locate(key).unwrap_or(0);
+            owned.entry(at).or_default().push(key.clone());
+        }
+
+        let mut replies = Vec::new();
+        for (at, keys) in owned {
+            let reply = self.nodes[at].request(narrow(buf, multi, &keys))?;
+            if reply::is_error(&reply) { return Ok(reply); }
+            replies.push(reply);
+        }
+        return Ok(match multi {
+            Multi::Get | Multi::Mget => merge::values(&replies, &keys),
+            Multi::BopMget => merge::bop_values(&replies, &keys),
+            Multi::BopSmget => {
+                let line = String::from_utf8_lossy(buf.split(|&b| b == b'\n').next().unwrap_or(b""));
+                let words: Vec<&str> = line.split_whitespace().collect();
+                let unique = words.last() == Some(&"unique");
+                let count = words.iter().rev()
+                                 .find_map(|w| w.parse::<usize>().ok())
+                                 .unwrap_or(0);
+                let desc = words.get(4)
+                                .and_then(|r| r.split_once(".."))
+                                .is_some_and(|(from, to)| merge::bkey_cmp(from, to) == Ordering::Greater);
+                merge::smget(&replies, count, desc, unique)
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        return words.iter().map(|w| w.to_string()).collect();
+    }
+
+    #[test]
+    fn finds_the_keys_of_multi_key_requests() {
+        assert!(keys_of(b"gets a b c\r\n") == Some((Multi::Get, strings(&["a", "b", "c"]))));
+        assert!(keys_of(b"mget 5 3\r\na b c\r\n") == Some((Multi::Mget, strings(&["a", "b", "c"]))));
+        assert!(keys_of(b"bop mget 3 2 0..10\r\na b\r\n") == Some((Multi::BopMget, strings(&["a", "b"]))));
+        assert!(keys_of(b"bop smget 3 2 0..10 5\r\na b\r\n") == Some((Multi::BopSmget, strings(&["a", "b"]))));
+        assert!(keys_of(b"bop get a 0..10\r\n").is_none());
+    }
+
+    #[test]
+    fn narrows_requests_to_some_keys() {
+        let keys = strings(&["a", "cc"]);
+        assert_eq!(narrow(b"gets a b cc\r\n", Multi::Get, &keys), b"gets a cc\r\n");
+        assert_eq!(narrow(b"mgets 6 3\r\na b cc\r\n", Multi::Mget, &keys), b"mgets 4 2\r\na cc\r\n");
+        assert_eq!(narrow(b"bop mget 6 3 0..10 efilter 2\r\na b cc\r\n", Multi::BopMget, &keys),
+                   b"bop mget 4 2 0..10 efilter 2\r\na cc\r\n");
+        assert_eq!(narrow(b"bop smget 6 3 10..0 5 unique\r\na b cc\r\n", Multi::BopSmget, &keys),
+                   b"bop smget 4 2 10..0 5 unique\r\na cc\r\n");
+    }
+
+    #[test]
+    fn finds_the_key_of_single_key_requests() {
+        assert_eq!(key_of(b"set k 0 0 1\r\nx\r\n").as_deref(), Some("k"));
+        assert_eq!(key_of(b"bop insert k 1 1\r\nx\r\n").as_deref(), Some("k"));
+        assert_eq!(key_of(b"mget 3 2\r\na b\r\n").as_deref(), Some("a"));
+        assert_eq!(key_of(b"stats\r\n"), None);
+        assert_eq!(key_of(b"flush_all\r\n"), None);
+    }
+
+    /* A node that answers every request with `answer` and passes on the keys it was asked for. */
+    fn node(answer: fn(&[&str]) -> String) -> (Transport, Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (asked, keys) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(conn.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                /* "get" names its keys on the command line, the others on a line of their own. */
+                let mut list = line.clone();
+                if line.starts_with("get ") {
+                    list = list.split_once(' ').unwrap().1.to_string();
+                } else {
+                    list.clear();
+                    reader.read_line(&mut list).unwrap();
+                }
+                let words: Vec<&str> = list.split_whitespace().collect();
+                asked.send(strings(&words)).unwrap();
+                conn.write_all(answer(&words).as_bytes()).unwrap();
+                line.clear();
+            }
+        });
+        return (Transport::TCP(addr, Default::default()), keys);
+    }
+
+    #[test]
+    fn scatters_gets_by_owner_and_merges_in_order() {
+        let answer = |keys: &[&str]| {
+            let mut reply = String::new();
+            for key in keys.iter().filter(|&&k| k != "h") {
+                reply += &format!("VALUE {} 0 {}\r\n{}\r\n\r\n", key, key.len() + 2, key);
+            }
+            return reply + "END\r\n";
+        };
+        let (one, asked_one) = node(answer);
+        let (two, asked_two) = node(answer);
+        let mut cluster = Cluster::new("test".to_string(), vec![one, two]);
+        let replies = cluster.write(b"get g f e d c b a h\r\n".to_vec());
+        assert_eq!(replies.len(), 1);
+        let expected: String = ["g", "f", "e", "d", "c", "b", "a"].iter()
+                                                                   .map(|k| format!("VALUE {} 0 3\r\n{}\r\n\r\n", k, k))
+                                                                   .collect();
+        assert_eq!(String::from_utf8_lossy(replies[0].1.as_ref().unwrap()), expected + "END\r\n");
+
+        let mut asked: Vec<String> = asked_one.try_iter().chain(asked_two.try_iter()).flatten().collect();
+        asked.sort();
+        assert_eq!(asked, strings(&["a", "b", "c", "d", "e", "f", "g", "h"]));
+    }
+
+    #[test]
+    fn scatters_smgets_and_sort_merges() {
+        let answer = |keys: &[&str]| {
+            let bkeys = |key: &str| match key { "a" => 4, "b" => 1, "c" => 3, _ => 2 };
+            let mut keys = keys.to_vec();
+            keys.sort_by_key(|k| bkeys(k));
+            let mut reply = format!("ELEMENTS {}\r\n", keys.len());
+            for key in keys.iter() {
+                reply += &format!("{} 0 {} 1 x\r\n", key, bkeys(key));
+            }
+            return reply + "MISSED_KEYS 0\r\nTRIMMED_KEYS 0\r\nEND\r\n";
+        };
+        let (one, _asked_one) = node(answer);
+        let (two, _asked_two) = node(answer);
+        let mut cluster = Cluster::new("test".to_string(), vec![one, two]);
+        let replies = cluster.write(b"bop smget 7 4 0..10 3\r\na b c d\r\n".to_vec());
+        assert_eq!(String::from_utf8_lossy(replies[0].1.as_ref().unwrap()),
+                   "ELEMENTS 3\r\nb 0 1 1 x\r\nd 0 2 1 x\r\nc 0 3 1 x\r\nMISSED_KEYS 0\r\nTRIMMED_KEYS 0\r\nEND\r\n");
+    }
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// SASL identity and mechanism choice, gathered once and reused on every connection.
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    /// Identity to act on behalf of, if not the username's own.
+    pub authzid: Option<String>,
+    /// Mechanism to insist on instead of the best one the server offers.
+    pub mech: Option<String>,
+}
+
+fn missing(what: &str) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("no SASL {} (use --user with --password-env or --password-file)", what))
+}
+
+/* ~/.arcus-cli/credentials holds "user = name" and "password = secret" lines; "#" starts a comment. */
+fn default_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(".arcus-cli").join("credentials"))
+}
+
+fn read_file(path: &Path) -> io::Result<(Option<String>, Option<String>)> {
+    let text = fs::read_to_string(path)?;
+    let (mut user, mut password) = (None, None);
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') { continue; }
+        match line.split_once('=').map(|(k, v)| (k.trim(), v.trim().to_string())) {
+            Some(("user", v)) | Some(("username", v)) => user = Some(v),
+            Some(("password", v)) => password = Some(v),
+            _ => return Err(io::Error::new(ErrorKind::InvalidData,
+                                           format!("{}: unexpected line \"{}\"", path.display(), line))),
+        }
+    }
+    return Ok((user, password));
+}
+
+impl Credentials {
+    /// Resolves the credentials from, in order: `--user`, `--password-env`, `--password-file`,
+    /// ~/.arcus-cli/credentials and finally an interactive prompt.
+    pub fn resolve(user: Option<String>, password_env: Option<&str>,
+                   password_file: Option<&Path>) -> io::Result<Credentials> {
+        let mut password = match password_env {
+            Some(var) => match env::var(var) {
+                Ok(value) => Some(value),
+                Err(err) => return Err(io::Error::new(ErrorKind::NotFound, format!("{}: {}", var, err))),
+            },
+            None => None,
+        };
+        if password.is_none() {
+            if let Some(path) = password_file {
+                let text = fs::read_to_string(path)
+                    .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+                password = Some(text.trim_end_matches(['\r', '\n']).to_string());
+            }
+        }
+
+        let mut user = user;
+        if user.is_none() || password.is_none() {
+            if let Some(path) = default_file().filter(|path| path.exists()) {
+                let (file_user, file_password) = read_file(&path)?;
+                /* The file's password only belongs to the file's user. */
+                if user.is_none() || user == file_user {
+                    user = user.or(file_user);
+                    password = password.or(file_password);
+                }
+            }
+        }
+
+        let username = match user {
+            Some(user) => user,
+            None if io::stdin().is_terminal() => {
+                let mut username = String::new();
+                print!("username: ");
+                io::stdout().flush()?;
+                io::stdin().read_line(&mut username)?;
+                username.trim().to_string()
+            },
+            None => return Err(missing("user")),
+        };
+        let password = match password {
+            Some(password) => password,
+            None => rpassword::prompt_password("password: ").map_err(|_| missing("password"))?,
+        };
+        return Ok(Credentials { username, password, ..Default::default() });
+    }
+}
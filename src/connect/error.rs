@@ -0,0 +1,121 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::time::Duration;
+
+/* Exit statuses, so scripts can tell failures apart. 1 stays "an error reply or I/O error". */
+pub const EXIT_RESOLVE: i32 = 3;
+pub const EXIT_REFUSED: i32 = 4;
+pub const EXIT_AUTH: i32 = 5;
+pub const EXIT_PROTOCOL: i32 = 6;
+pub const EXIT_TIMEOUT: i32 = 7;
+pub const EXIT_TLS: i32 = 8;
+
+/// Why a connection could not be set up.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The address did not resolve.
+    Resolve(String, io::Error),
+    /// No resolved address took the connection (refused, unreachable, ...).
+    Refused(String, io::Error),
+    /// Connecting, or a handshake on the new connection, took too long: what
+    /// was under way and the limit, if one was set.
+    Timeout(String, &'static str, Option<Duration>),
+    /// The TLS handshake failed, for instance on an untrusted certificate.
+    Tls(String, io::Error),
+    /// The server turned down the SASL exchange.
+    Auth(String, String),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Resolve(addr, err) => write!(f, "{}: {}", addr, err),
+            ConnectError::Refused(addr, err) => write!(f, "{}: {}", addr, err),
+            ConnectError::Timeout(addr, during, Some(limit)) => write!(f, "{}: timed out after {:?} {}", addr, limit, during),
+            ConnectError::Timeout(addr, during, None) => write!(f, "{}: timed out {}", addr, during),
+            ConnectError::Tls(addr, err) => write!(f, "{}: {}", addr, err),
+            ConnectError::Auth(addr, reason) => write!(f, "{}: SASL authentication failed: {}", addr, reason),
+        }
+    }
+}
+
+impl ConnectError {
+    /// A failed connection attempt: timed out `during` something if `err`
+    /// says so, refused otherwise.
+    pub fn failed(addr: &str, err: io::Error, during: &'static str, limit: Option<Duration>) -> ConnectError {
+        match err.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => ConnectError::Timeout(addr.to_string(), during, limit),
+            _ => ConnectError::Refused(addr.to_string(), err),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+/// The server said something the protocol does not allow at that point.
+#[derive(Debug)]
+pub struct ProtocolError(pub String);
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Anything that can go wrong between sending a request and having its reply.
+#[derive(Debug)]
+pub enum Error {
+    Connect(ConnectError),
+    Protocol(ProtocolError),
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Connect(ConnectError::Resolve(..)) => EXIT_RESOLVE,
+            Error::Connect(ConnectError::Refused(..)) => EXIT_REFUSED,
+            Error::Connect(ConnectError::Timeout(..)) => EXIT_TIMEOUT,
+            Error::Connect(ConnectError::Tls(..)) => EXIT_TLS,
+            Error::Connect(ConnectError::Auth(..)) => EXIT_AUTH,
+            Error::Protocol(_) => EXIT_PROTOCOL,
+            Error::Io(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(err) => write!(f, "{}", err),
+            Error::Protocol(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConnectError> for Error {
+    fn from(err: ConnectError) -> Error {
+        Error::Connect(err)
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(err: ProtocolError) -> Error {
+        Error::Protocol(err)
+    }
+}
+
+/* Replies that do not parse come back from the readers as InvalidData. */
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match err.kind() {
+            ErrorKind::InvalidData => Error::Protocol(ProtocolError(err.to_string())),
+            _ => Error::Io(err),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Something a transport did on its own that the user may want to hear
+/// about. None of these fail the request at hand.
+#[derive(Debug)]
+pub enum Event {
+    /// A connection attempt failed; the next one follows after `backoff`.
+    Retrying { address: String, error: io::Error, backoff: Duration },
+    /// A connection is back after failed attempts.
+    Reconnected { address: String },
+    /// A read-only request lost its reply to a dropped connection and is sent again.
+    Replaying { address: String, request: String },
+    /// The server accepted the SASL credentials.
+    Authenticated { address: String, user: String, mech: String },
+    /// A resolved UDP address did not work out; the next one is tried.
+    Unusable { address: SocketAddr, error: io::Error },
+}
+
+impl Event {
+    /// Whether the event reports a failure, even one that was got around.
+    pub fn is_error(&self) -> bool {
+        matches!(self, Event::Retrying { .. } | Event::Unusable { .. })
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Retrying { address, error, backoff } =>
+                write!(f, "{}: {} (retrying in {:?})", address, error, backoff),
+            Event::Reconnected { address } => write!(f, "Reconnected to {}", address),
+            Event::Replaying { request, .. } => write!(f, "Replaying \"{}\"", request),
+            Event::Authenticated { user, mech, .. } => write!(f, "SASL: authenticated as {} with {}", user, mech),
+            Event::Unusable { address, error } => write!(f, "{}: {}", address, error),
+        }
+    }
+}
+
+/// Where the transports report their events; without one they stay quiet.
+pub type Log = Arc<dyn Fn(&Event) + Send + Sync>;
@@ -0,0 +1,125 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+
+/* Points per node on the ring, as in the ARCUS Java and C clients. */
+const REPLICAS: usize = 160;
+
+/// Ketama consistent-hash ring over `host:port` node names, laid out as the
+/// ARCUS Java client lays it out.
+#[derive(Default)]
+pub struct Ketama {
+    points: Vec<(u32, usize)>,
+}
+
+fn point(digest: &[u8; 16], h: usize) -> u32 {
+    (digest[3 + h * 4] as u32) << 24
+        | (digest[2 + h * 4] as u32) << 16
+        | (digest[1 + h * 4] as u32) << 8
+        | digest[h * 4] as u32
+}
+
+/* The Java client hashes the text of the node's InetSocketAddress: "ip:port",
+   with IPv6 addresses uncompressed and unbracketed, and IPv4 preferred. Names
+   that do not resolve, such as Unix socket paths, are hashed as they are. */
+fn resolve(name: &str) -> String {
+    let addrs: Vec<SocketAddr> = match name.to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(_) => return name.to_string(),
+    };
+    return match addrs.iter().find(|a| a.is_ipv4()).or(addrs.first()) {
+        Some(SocketAddr::V4(addr)) => addr.to_string(),
+        Some(SocketAddr::V6(addr)) => {
+            let segments: Vec<String> = addr.ip().segments().iter().map(|s| format!("{:x}", s)).collect();
+            format!("{}:{}", segments.join(":"), addr.port())
+        },
+        None => name.to_string(),
+    };
+}
+
+impl Ketama {
+    pub fn new(nodes: &[String]) -> Ketama {
+        let mut points = Vec::with_capacity(nodes.len() * REPLICAS);
+        for (index, node) in nodes.iter().enumerate() {
+            let node = resolve(node);
+            for i in 0..REPLICAS / 4 {
+                let digest = md5::compute(format!("{}-{}", node, i)).0;
+                for h in 0..4 {
+                    points.push((point(&digest, h), index));
+                }
+            }
+        }
+        points.sort();
+        return Ketama { points };
+    }
+
+    /// Index of the node that owns `key`.
+    pub fn locate(&self, key: &str) -> Option<usize> {
+        if self.points.is_empty() { return None; }
+        let hash = point(&md5::compute(key).0, 0);
+        let at = self.points.partition_point(|&(p, _)| p < hash);
+        return Some(self.points[at % self.points.len()].1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(count: usize) -> Vec<String> {
+        return (1..=count).map(|n| format!("10.0.0.{}:11211", n)).collect();
+    }
+
+    /* Points and owners as the Java client's KetamaNodeLocator works them out
+       for the same nodes: MD5 of "<ip:port>-<n>", four little-endian points per
+       digest, and the first point at or after the key's hash. */
+    #[test]
+    fn places_points_like_the_java_client() {
+        let digest = md5::compute("10.0.0.1:11211-0").0;
+        let points: Vec<u32> = (0..4).map(|h| point(&digest, h)).collect();
+        assert_eq!(points, [1644766326, 266575842, 1549369152, 2004188753]);
+        assert_eq!(point(&md5::compute("foo").0, 0), 3675831724);
+        assert_eq!(Ketama::new(&nodes(3)).points.len(), 3 * REPLICAS);
+    }
+
+    #[test]
+    fn locates_keys_like_the_java_client() {
+        let ring = Ketama::new(&nodes(3));
+        let golden = [
+            ("foo", 2), ("bar", 0), ("baz", 2), ("user:1", 2), ("user:2", 2), ("user:3", 0),
+            ("arcus", 0), ("prefix:key", 1), ("a", 2), ("0", 0), ("key-with-long-name-0123456789", 0), ("é", 0),
+        ];
+        for (key, node) in golden {
+            assert_eq!(ring.locate(key), Some(node), "{}", key);
+        }
+    }
+
+    #[test]
+    fn moves_keys_only_to_a_new_node() {
+        let before = Ketama::new(&nodes(3));
+        let after = Ketama::new(&nodes(4));
+        for (key, node) in [("foo", 2), ("baz", 3), ("user:1", 3), ("key-with-long-name-0123456789", 3), ("bar", 0)] {
+            assert_eq!(after.locate(key), Some(node), "{}", key);
+        }
+        for key in (0..1000).map(|n| format!("key:{}", n)) {
+            let moved = after.locate(&key).unwrap();
+            assert!(moved == 3 || Some(moved) == before.locate(&key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn resolves_node_names() {
+        assert_eq!(resolve("10.0.0.1:11211"), "10.0.0.1:11211");
+        assert_eq!(resolve("[::1]:11211"), "0:0:0:0:0:0:0:1:11211");
+        assert_eq!(resolve("[fe80::1:2]:11211"), "fe80:0:0:0:0:0:1:2:11211");
+        assert_eq!(resolve("/tmp/arcus.sock"), "/tmp/arcus.sock");
+        assert_eq!(resolve("localhost:11211"), "127.0.0.1:11211");
+        let by_name = Ketama::new(&["localhost:11211".to_string(), "10.0.0.2:11211".to_string()]);
+        let by_ip = Ketama::new(&["127.0.0.1:11211".to_string(), "10.0.0.2:11211".to_string()]);
+        assert_eq!(by_name.points, by_ip.points);
+    }
+
+    #[test]
+    fn locates_nothing_without_nodes() {
+        assert_eq!(Ketama::new(&[]).locate("foo"), None);
+        assert_eq!(Ketama::default().locate("foo"), None);
+    }
+}
@@ -0,0 +1,213 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+fn lines(reply: &[u8]) -> impl Iterator<Item = String> + '_ {
+    reply.split(|&b| b == b'\n')
+         .filter(|l| !l.is_empty())
+         .map(|l| String::from_utf8_lossy(l).trim_end_matches('\r').to_string())
+}
+
+/// Merges `get`/`mget` replies, in the order the keys were asked for.
+pub fn values(replies: &[Vec<u8>], keys: &[String]) -> Vec<u8> {
+    let mut blocks: HashMap<String, &[u8]> = HashMap::new();
+    for reply in replies {
+        let mut rest = reply.as_slice();
+        while let Some(end) = rest.windows(2).position(|w| w == b"\r\n") {
+            let head = String::from_utf8_lossy(&rest[..end]);
+            let words: Vec<&str> = head.split_whitespace().collect();
+            if words.len() < 4 || words[0] != "VALUE" { break; }
+            let bytes = words[3].parse::<usize>().unwrap_or(0);
+            let len = (end + 2 + bytes + 2).min(rest.len());
+            blocks.insert(words[1].to_string(), &rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+    let mut out = Vec::new();
+    for key in keys {
+        if let Some(block) = blocks.get(key) { out.extend_from_slice(block); }
+    }
+    out.extend_from_slice(b"END\r\n");
+    return out;
+}
+
+/// Merges `bop mget` replies, in the order the keys were asked for.
+pub fn bop_values(replies: &[Vec<u8>], keys: &[String]) -> Vec<u8> {
+    let mut blocks: HashMap<String, String> = HashMap::new();
+    for reply in replies {
+        let mut key = None;
+        for line in lines(reply) {
+            if line == "END" { break; }
+            if let Some(head) = line.strip_prefix("VALUE ") {
+                key = head.split_whitespace().next().map(str::to_string);
+            }
+            if let Some(key) = key.as_ref() {
+                let block = blocks.entry(key.clone()).or_default();
+                block.push_str(&line);
+                block.push_str("\r\n");
+            }
+        }
+    }
+    let mut out = String::new();
+    for key in keys {
+        if let Some(block) = blocks.get(key) { out.push_str(block); }
+    }
+    out.push_str("END\r\n");
+    return out.into_bytes();
+}
+
+/// Orders b+tree keys: unsigned integers numerically, hex byte strings bytewise.
+pub fn bkey_cmp(a: &str, b: &str) -> Ordering {
+    match (a.strip_prefix("0x"), b.strip_prefix("0x")) {
+        (Some(a), Some(b)) => a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()),
+        (None, None) => match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        },
+        _ => a.cmp(b),
+    }
+}
+
+/// Merges `bop smget` replies into one sort-merged result of at most `count` elements.
+pub fn smget(replies: &[Vec<u8>], count: usize, desc: bool, unique: bool) -> Vec<u8> {
+    let mut header = "ELEMENTS";
+    let mut elements: Vec<String> = Vec::new();
+    let mut missed: Vec<String> = Vec::new();
+    let mut trimmed: Vec<String> = Vec::new();
+    let mut statuses: Vec<String> = Vec::new();
+    for reply in replies {
+        let mut section = "";
+        let mut left = 0;
+        for line in lines(reply) {
+            if left > 0 {
+                left -= 1;
+                match section {
+                    "MISSED_KEYS" => missed.push(line),
+                    "TRIMMED_KEYS" => trimmed.push(line),
+                    _ => elements.push(line),
+                }
+                continue;
+            }
+            let (name, n) = line.split_once(' ').unwrap_or((&line, ""));
+            section = match name {
+                "ELEMENTS" => "ELEMENTS",
+                "VALUE" => { header = "VALUE"; "ELEMENTS" },
+                "MISSED_KEYS" => "MISSED_KEYS",
+                "TRIMMED_KEYS" => "TRIMMED_KEYS",
+                _ => { statuses.push(line); continue }
+            };
+            left = n.trim().parse().unwrap_or(0);
+        }
+    }
+
+    let bkey = |e: &String| e.split_whitespace().nth(2).unwrap_or("").to_string();
+    elements.sort_by(|a, b| {
+        let order = bkey_cmp(&bkey(a), &bkey(b));
+        if desc { order.reverse() } else { order }
+    });
+    let mut duplicated = statuses.iter().any(|s| s.starts_with("DUPLICATED"));
+    duplicated |= elements.windows(2).any(|w| bkey(&w[0]) == bkey(&w[1]));
+    if unique {
+        elements.dedup_by(|a, b| bkey(a) == bkey(b));
+    }
+    elements.truncate(count);
+
+    let trim = statuses.iter().any(|s| s.ends_with("TRIMMED"));
+    let status = match (header, duplicated && !unique, trim) {
+        ("VALUE", true, true) => "DUPLICATED_TRIMMED",
+        ("VALUE", false, true) => "TRIMMED",
+        (_, true, _) => "DUPLICATED",
+        _ => "END",
+    };
+
+    let mut out = format!("{} {}\r\n", header, elements.len());
+    for e in elements.iter() { out += &format!("{}\r\n", e); }
+    out += &format!("MISSED_KEYS {}\r\n", missed.len());
+    for k in missed.iter() { out += &format!("{}\r\n", k); }
+    if header == "ELEMENTS" {
+        out += &format!("TRIMMED_KEYS {}\r\n", trimmed.len());
+        for k in trimmed.iter() { out += &format!("{}\r\n", k); }
+    }
+    out += &format!("{}\r\n", status);
+    return out.into_bytes();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        return keys.iter().map(|k| k.to_string()).collect();
+    }
+
+    fn text(reply: Vec<u8>) -> String {
+        return String::from_utf8(reply).unwrap();
+    }
+
+    #[test]
+    fn merges_values_in_key_order() {
+        let one = b"VALUE a 0 8\r\nx\r\nEND\r\n\r\nVALUE c 1 1 7\r\nz\r\nEND\r\n".to_vec();
+        let two = b"VALUE b 2 1\r\ny\r\nEND\r\n".to_vec();
+        assert_eq!(text(values(&[one, two], &keys(&["c", "b", "missing", "a"]))),
+                   "VALUE c 1 1 7\r\nz\r\nVALUE b 2 1\r\ny\r\nVALUE a 0 8\r\nx\r\nEND\r\n\r\nEND\r\n");
+        assert_eq!(text(values(&[b"END\r\n".to_vec()], &keys(&["a"]))), "END\r\n");
+    }
+
+    #[test]
+    fn merges_bop_values_in_key_order() {
+        let one = b"VALUE a OK 0 2\r\nELEMENT 1 1 x\r\nELEMENT 2 0x01 1 y\r\nVALUE c NOT_FOUND\r\nEND\r\n".to_vec();
+        let two = b"VALUE b TRIMMED 0 1\r\nELEMENT 9 1 z\r\nEND\r\n".to_vec();
+        assert_eq!(text(bop_values(&[one, two], &keys(&["c", "b", "a"]))),
+                   "VALUE c NOT_FOUND\r\nVALUE b TRIMMED 0 1\r\nELEMENT 9 1 z\r\n\
+                    VALUE a OK 0 2\r\nELEMENT 1 1 x\r\nELEMENT 2 0x01 1 y\r\nEND\r\n");
+    }
+
+    #[test]
+    fn orders_bkeys() {
+        assert_eq!(bkey_cmp("9", "10"), Ordering::Less);
+        assert_eq!(bkey_cmp("10", "10"), Ordering::Equal);
+        assert_eq!(bkey_cmp("0x0A", "0x0b"), Ordering::Less);
+        assert_eq!(bkey_cmp("0xFF", "0xff"), Ordering::Equal);
+        assert_eq!(bkey_cmp("0x01", "0x0100"), Ordering::Less);
+        assert_eq!(bkey_cmp("0x02", "0x0100"), Ordering::Greater);
+    }
+
+    fn sorted() -> [Vec<u8>; 2] {
+        return [
+            b"ELEMENTS 2\r\na 0 1 1 x\r\na 0 3 1 y\r\nMISSED_KEYS 1\r\nc NOT_FOUND\r\nTRIMMED_KEYS 0\r\nEND\r\n".to_vec(),
+            b"ELEMENTS 2\r\nb 0 1 1 z\r\nb 0 2 1 w\r\nMISSED_KEYS 0\r\nTRIMMED_KEYS 1\r\nd 4\r\nEND\r\n".to_vec(),
+        ];
+    }
+
+    #[test]
+    fn sort_merges_smget_replies() {
+        assert_eq!(text(smget(&sorted(), 10, false, false)),
+                   "ELEMENTS 4\r\na 0 1 1 x\r\nb 0 1 1 z\r\nb 0 2 1 w\r\na 0 3 1 y\r\n\
+                    MISSED_KEYS 1\r\nc NOT_FOUND\r\nTRIMMED_KEYS 1\r\nd 4\r\nDUPLICATED\r\n");
+    }
+
+    #[test]
+    fn sort_merges_descending_up_to_count() {
+        assert_eq!(text(smget(&sorted(), 3, true, false)),
+                   "ELEMENTS 3\r\na 0 3 1 y\r\nb 0 2 1 w\r\na 0 1 1 x\r\n\
+                    MISSED_KEYS 1\r\nc NOT_FOUND\r\nTRIMMED_KEYS 1\r\nd 4\r\nDUPLICATED\r\n");
+    }
+
+    #[test]
+    fn drops_duplicate_bkeys_when_unique() {
+        assert_eq!(text(smget(&sorted(), 2, false, true)),
+                   "ELEMENTS 2\r\na 0 1 1 x\r\nb 0 2 1 w\r\n\
+                    MISSED_KEYS 1\r\nc NOT_FOUND\r\nTRIMMED_KEYS 1\r\nd 4\r\nEND\r\n");
+    }
+
+    #[test]
+    fn reports_duplicated_and_trimmed_old_style() {
+        let replies = [
+            b"VALUE 1\r\na 0 1 1 x\r\nMISSED_KEYS 0\r\nTRIMMED\r\n".to_vec(),
+            b"VALUE 1\r\nb 0 1 1 y\r\nMISSED_KEYS 0\r\nEND\r\n".to_vec(),
+        ];
+        assert_eq!(text(smget(&replies, 10, false, false)),
+                   "VALUE 2\r\na 0 1 1 x\r\nb 0 1 1 y\r\nMISSED_KEYS 0\r\nDUPLICATED_TRIMMED\r\n");
+        assert_eq!(text(smget(&replies, 10, false, true)),
+                   "VALUE 1\r\na 0 1 1 x\r\nMISSED_KEYS 0\r\nTRIMMED\r\n");
+    }
+}
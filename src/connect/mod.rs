@@ -1,9 +1,28 @@
+pub mod cluster;
+pub mod credentials;
+pub mod error;
+pub mod event;
+pub mod ketama;
+pub mod merge;
+pub mod reply;
+pub mod stream;
 pub mod tcp;
+pub mod tls;
 pub mod udp;
 pub mod unix;
+pub mod zookeeper;
 
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use rustls::ClientConfig;
+use self::credentials::Credentials;
+use self::error::Error;
+use self::event::Log;
+use self::reply::Expect;
+use self::stream::{Reconnect, Timeouts};
 use self::tcp::TcpClient;
-use self::udp::UdpClient;
+use self::udp::{Framing, UdpClient};
 use self::unix::UnixClient;
 
 pub enum Transport {
@@ -13,32 +32,134 @@ pub enum Transport {
 }
 
 impl Transport {
-    pub fn setting(&mut self, rqid: u16, time: u64, _auth: bool) {
+    pub fn setting(&mut self, rqid: u16, time: u64, auth: Option<Credentials>) {
         match self {
             Transport::TCP(_, clnt) => {
-                clnt.auth = _auth;
+                clnt.auth = auth;
             }
             Transport::UDP(_, clnt) => {
                 clnt.rqid = rqid;
                 clnt.time = time;
-                clnt.auth = _auth;
+                clnt.auth = auth;
             },
             Transport::UNIX(_, clnt) => {
-                clnt.auth = _auth;
+                clnt.auth = auth;
             }
         }
     }
 
-    pub fn write(&mut self, mut line: String) {
-        if !line.is_empty() && !line.ends_with('\r') { line.push('\r'); }
-        line.push('\n');
+    /// Wraps TCP connections in TLS; other transports are left as they are.
+    pub fn secure(&mut self, config: Arc<ClientConfig>) {
+        if let Transport::TCP(_, clnt) = self {
+            clnt.options = Some(config);
+        }
+    }
+
+    /// Sends UDP requests from `addr`; other transports are left as they are.
+    pub fn bind(&mut self, addr: SocketAddr) {
+        if let Transport::UDP(_, clnt) = self {
+            clnt.bind = Some(addr);
+        }
+    }
+
+    /// Sets how UDP datagrams are framed; other transports are left as they are.
+    pub fn framing(&mut self, framing: Framing) {
+        if let Transport::UDP(_, clnt) = self {
+            clnt.framing = framing;
+        }
+    }
+
+    /// Detects the UDP framing the server expects; `None` for other transports.
+    pub fn probe(&mut self) -> Option<io::Result<Framing>> {
+        match self {
+            Transport::UDP(_, clnt) => Some(clnt.probe()),
+            _ => None,
+        }
+    }
+
+    /// Sets how stream transports reconnect; UDP has no connection to lose.
+    pub fn retry(&mut self, retry: Reconnect) {
+        match self {
+            Transport::TCP(_, clnt) => clnt.retry = retry,
+            Transport::UNIX(_, clnt) => clnt.retry = retry,
+            Transport::UDP(..) => (),
+        }
+    }
+
+    /// Sets the socket timeouts of stream transports; UDP has its own reply timeout.
+    pub fn timeouts(&mut self, timeouts: Timeouts) {
         match self {
-            Transport::TCP(addr, clnt) =>
-                if clnt.write(line) { clnt.connect(addr) },
-            Transport::UDP(addr, clnt) =>
-                if clnt.write(line) { clnt.connect(addr) },
-            Transport::UNIX(addr, clnt) =>
-                if clnt.write(line) { clnt.connect(addr) },
+            Transport::TCP(_, clnt) => clnt.timeouts = timeouts,
+            Transport::UNIX(_, clnt) => clnt.timeouts = timeouts,
+            Transport::UDP(..) => (),
+        }
+    }
+
+    /// Reports what the transport does on its own, such as reconnecting, to `log`.
+    pub fn log(&mut self, log: Log) {
+        match self {
+            Transport::TCP(_, clnt) => clnt.log = Some(log),
+            Transport::UNIX(_, clnt) => clnt.log = Some(log),
+            Transport::UDP(_, clnt) => clnt.log = Some(log),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Transport::TCP(addr, _) | Transport::UDP(addr, _) | Transport::UNIX(addr, _) => addr,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        match self {
+            Transport::TCP(_, clnt) => clnt.is_connected(),
+            Transport::UDP(_, clnt) => clnt.is_connected(),
+            Transport::UNIX(_, clnt) => clnt.is_connected(),
+        }
+    }
+
+    /// Opens the connection if it is down.
+    pub fn connect(&mut self) -> Result<(), Error> {
+        if self.is_connected() { return Ok(()); }
+        return match self {
+            Transport::TCP(addr, clnt) => clnt.connect(addr),
+            Transport::UDP(addr, clnt) => Ok(clnt.connect(addr)?),
+            Transport::UNIX(addr, clnt) => clnt.connect(addr),
+        };
+    }
+
+    /// Sends several requests without waiting for replies in between, and
+    /// returns their replies in order. UDP sends them one at a time.
+    pub fn pipeline(&mut self, mut reqs: Vec<Vec<u8>>) -> Vec<Result<Vec<u8>, Error>> {
+        for buf in reqs.iter_mut() {
+            if !buf.ends_with(b"\r\n") {
+                if !buf.ends_with(b"\r") { buf.push(b'\r'); }
+                buf.push(b'\n');
+            }
+        }
+        return match self {
+            Transport::TCP(addr, clnt) => clnt.pipeline(addr, &reqs),
+            Transport::UNIX(addr, clnt) => clnt.pipeline(addr, &reqs),
+            Transport::UDP(..) => reqs.into_iter().map(|buf| self.request(buf)).collect(),
+        };
+    }
+
+    /// Sends one request and returns its complete reply, which is empty if none
+    /// is expected. An empty request only opens the connection if it is down.
+    pub fn request(&mut self, mut buf: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if buf.is_empty() {
+            self.connect()?;
+            return Ok(Vec::new());
+        }
+        if !buf.ends_with(b"\r\n") {
+            if !buf.ends_with(b"\r") { buf.push(b'\r'); }
+            buf.push(b'\n');
         }
+        let expect = Expect::of(&buf);
+        return match self {
+            Transport::TCP(addr, clnt) => clnt.request(addr, &buf, expect),
+            Transport::UDP(addr, clnt) => clnt.request(addr, &buf, expect),
+            Transport::UNIX(addr, clnt) => clnt.request(addr, &buf, expect),
+        };
     }
 }
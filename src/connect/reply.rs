@@ -0,0 +1,326 @@
+use std::io::{self, BufRead, ErrorKind};
+
+/// What a request expects back from the server.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Expect {
+    /// Empty, `noreply` and `pipe` requests get no reply.
+    Nothing,
+    /// `VALUE <key> <flags> <bytes> [<cas>]` blocks with their data, then `END`.
+    Values,
+    /// Lines up to `END`, as for `stats`, `scan` or a `config` query.
+    Listing,
+    /// `VALUE` and its element lines, then the line that closes them; for
+    /// `bop smget` also the missed and trimmed keys.
+    Elements(Shape),
+    /// `bop mget`: `VALUE` and `ELEMENT` lines per key, then `END`.
+    Collections,
+    /// A status line, or a multi-line reply announced by its first line.
+    Status,
+}
+
+/// Element lines, by the request that asked for them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shape {
+    /// `<bytes> <data>`
+    List,
+    /// `<field> <bytes> <data>`
+    Map,
+    /// `<bkey> [<eflag>] <bytes> <data>`
+    Btree,
+    /// `<key> <flags> <bkey> [<eflag>] <bytes> <data>`
+    Sorted,
+}
+
+impl Expect {
+    pub fn of(req: &[u8]) -> Expect {
+        let line = req.split(|&b| b == b'\n').next().unwrap_or(b"");
+        let line = String::from_utf8_lossy(line);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if quiet(&words) {
+            return Expect::Nothing;
+        }
+        match words.as_slice() {
+            [] => Expect::Nothing,
+            ["get" | "gets" | "mget" | "mgets", ..] => Expect::Values,
+            ["lop" | "sop", "get", ..] => Expect::Elements(Shape::List),
+            ["mop", "get", ..] => Expect::Elements(Shape::Map),
+            ["bop", "get" | "gbp" | "pwg", ..] => Expect::Elements(Shape::Btree),
+            ["bop", "smget", ..] => Expect::Elements(Shape::Sorted),
+            ["bop", "mget", ..] => Expect::Collections,
+            ["stats", ..] | ["scan", ..] | ["help", ..] | ["config", _] | ["cmdlog", "stats"] => Expect::Listing,
+            _ => Expect::Status,
+        }
+    }
+}
+
+/* `noreply` and `pipe` are options only as the last word of a command that
+   takes them, and after its key; anywhere else they are keys or values. */
+fn quiet(words: &[&str]) -> bool {
+    let key = match words {
+        ["set" | "cas" | "add" | "append" | "prepend" | "replace" | "delete" | "incr" | "decr" | "flush_prefix", ..] => 1,
+        ["lop" | "sop" | "mop" | "bop", ..] => 2,
+        ["flush_all", ..] => 0,
+        _ => return false,
+    };
+    return words.len() > key + 1 && matches!(words.last(), Some(&"noreply") | Some(&"pipe"));
+}
+
+/// Whether a reply reports a failed request.
+pub fn is_error(reply: &[u8]) -> bool {
+    reply.starts_with(b"ERROR") || reply.starts_with(b"CLIENT_ERROR") || reply.starts_with(b"SERVER_ERROR")
+}
+
+fn line<R: BufRead>(rbuf: &mut R, reply: &mut Vec<u8>) -> io::Result<String> {
+    let start = reply.len();
+    if rbuf.read_until(b'\n', reply)? == 0 {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed by server"));
+    }
+    let line = String::from_utf8_lossy(&reply[start..]);
+    return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+}
+
+/* Reads one word and the space after it. */
+fn word<R: BufRead>(rbuf: &mut R, reply: &mut Vec<u8>) -> io::Result<String> {
+    let start = reply.len();
+    rbuf.read_until(b' ', reply)?;
+    if reply.last() != Some(&b' ') || reply[start..].contains(&b'\n') {
+        return Err(io::Error::new(ErrorKind::InvalidData, "malformed element line"));
+    }
+    return Ok(String::from_utf8_lossy(&reply[start..reply.len() - 1]).to_string());
+}
+
+/* Reads `bytes` of data and the "\r\n" after them. */
+fn data<R: BufRead>(rbuf: &mut R, reply: &mut Vec<u8>, bytes: usize) -> io::Result<()> {
+    let start = reply.len();
+    reply.resize(start + bytes + 2, 0);
+    return rbuf.read_exact(&mut reply[start..]);
+}
+
+/* Reads one element line by the byte count in it, since its data may hold
+   anything, line breaks and closing lines included. */
+fn element<R: BufRead>(rbuf: &mut R, reply: &mut Vec<u8>, shape: Shape) -> io::Result<()> {
+    let before = match shape {
+        Shape::List => 0,
+        Shape::Map | Shape::Btree => 1,
+        Shape::Sorted => 3,
+    };
+    for _ in 0..before {
+        word(rbuf, reply)?;
+    }
+    let mut bytes = word(rbuf, reply)?;
+    if matches!(shape, Shape::Btree | Shape::Sorted) && bytes.starts_with("0x") {
+        bytes = word(rbuf, reply)?;
+    }
+    let bytes = bytes.parse::<usize>()
+                     .map_err(|_| io::Error::new(ErrorKind::InvalidData, "malformed element line"))?;
+    return data(rbuf, reply, bytes);
+}
+
+/* The count in the word at `at` of a header line. */
+fn count(head: &str, at: usize) -> io::Result<usize> {
+    let mut words = head.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    return words.nth(at - 1)
+                .and_then(|c| c.parse::<usize>().ok())
+                .ok_or(io::Error::new(ErrorKind::InvalidData, format!("malformed {} line", name)));
+}
+
+/// Reads exactly one complete reply.
+pub fn read<R: BufRead>(rbuf: &mut R, expect: Expect) -> io::Result<Vec<u8>> {
+    let mut reply = Vec::new();
+    if expect == Expect::Nothing { return Ok(reply); }
+
+    let first = line(rbuf, &mut reply)?;
+    if is_error(first.as_bytes()) { return Ok(reply); }
+    match expect {
+        Expect::Values => {
+            let mut head = first;
+            while head.starts_with("VALUE ") {
+                let bytes = head.split_whitespace()
+                                .nth(3)
+                                .and_then(|b| b.parse::<usize>().ok())
+                                .ok_or(io::Error::new(ErrorKind::InvalidData, "malformed VALUE line"))?;
+                data(rbuf, &mut reply, bytes)?;
+                head = line(rbuf, &mut reply)?;
+            }
+        },
+        Expect::Listing => {
+            let single = first.split_whitespace().next().is_some_and(|w| {
+                w.bytes().all(|b| b.is_ascii_uppercase() || b == b'_') && !matches!(w, "STAT" | "ITEM" | "PREFIX" | "KEYS")
+            });
+            let mut head = first;
+            while !single && head != "END" {
+                head = line(rbuf, &mut reply)?;
+            }
+        },
+        Expect::Elements(shape) => {
+            let words: Vec<&str> = first.split_whitespace().collect();
+            let elements = match (shape, words.as_slice()) {
+                (Shape::Sorted, ["ELEMENTS" | "VALUE", _]) => count(&first, 1)?,
+                (Shape::Sorted, _) => return Ok(reply),
+                (_, ["VALUE", _, _]) => count(&first, 2)?,
+                (_, ["VALUE", _, _, _, _]) => count(&first, 3)?,
+                _ => return Ok(reply),
+            };
+            for _ in 0..elements {
+                element(rbuf, &mut reply, shape)?;
+            }
+            let mut head = line(rbuf, &mut reply)?;
+            while shape == Shape::Sorted && (head.starts_with("MISSED_KEYS ") || head.starts_with("TRIMMED_KEYS ")) {
+                for _ in 0..count(&head, 1)? {
+                    line(rbuf, &mut reply)?;
+                }
+                head = line(rbuf, &mut reply)?;
+            }
+        },
+        Expect::Collections => {
+            let mut head = first;
+            while head.starts_with("VALUE ") {
+                if head.split_whitespace().count() == 5 {
+                    for _ in 0..count(&head, 4)? {
+                        if word(rbuf, &mut reply)? != "ELEMENT" {
+                            return Err(io::Error::new(ErrorKind::InvalidData, "malformed element line"));
+                        }
+                        element(rbuf, &mut reply, Shape::Btree)?;
+                    }
+                }
+                head = line(rbuf, &mut reply)?;
+            }
+        },
+        Expect::Status => {
+            let words: Vec<&str> = first.split_whitespace().collect();
+            match words.first() {
+                Some(&"ATTR") | Some(&"STAT") => {
+                    let mut head = first.clone();
+                    while head != "END" {
+                        head = line(rbuf, &mut reply)?;
+                    }
+                },
+                Some(&"RESPONSE") => {
+                    let mut head = first.clone();
+                    while head != "END" && !head.starts_with("PIPE_ERROR") {
+                        head = line(rbuf, &mut reply)?;
+                    }
+                },
+                Some(&"SASL_CONTINUE") => { line(rbuf, &mut reply)?; },
+                _ => (),
+            }
+        },
+        Expect::Nothing => (),
+    }
+    return Ok(reply);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expects_nothing_for_trailing_options() {
+        assert_eq!(Expect::of(b"set k 0 0 2 noreply\r\nhi\r\n"), Expect::Nothing);
+        assert_eq!(Expect::of(b"bop insert k 1 2 pipe\r\nhi\r\n"), Expect::Nothing);
+        assert_eq!(Expect::of(b"delete k noreply"), Expect::Nothing);
+        assert_eq!(Expect::of(b"flush_all noreply"), Expect::Nothing);
+    }
+
+    #[test]
+    fn reads_options_as_keys_elsewhere() {
+        assert_eq!(Expect::of(b"set pipe 0 0 2\r\nhi\r\n"), Expect::Status);
+        assert_eq!(Expect::of(b"get pipe"), Expect::Values);
+        assert_eq!(Expect::of(b"get pipe k"), Expect::Values);
+        assert_eq!(Expect::of(b"delete noreply"), Expect::Status);
+        assert_eq!(Expect::of(b"lop get noreply 0"), Expect::Elements(Shape::List));
+        assert_eq!(Expect::of(b"bop create pipe 0 0 10"), Expect::Status);
+        assert_eq!(Expect::of(b"set k 0 0 7\r\nnoreply\r\n"), Expect::Status);
+        assert_eq!(Expect::of(b"version"), Expect::Status);
+    }
+
+    #[test]
+    fn expects_elements_by_collection() {
+        assert_eq!(Expect::of(b"lop get k 0..-1"), Expect::Elements(Shape::List));
+        assert_eq!(Expect::of(b"mop get k 2 delete\r\nf1 f2\r\n"), Expect::Elements(Shape::Map));
+        assert_eq!(Expect::of(b"bop pwg k 10 1"), Expect::Elements(Shape::Btree));
+        assert_eq!(Expect::of(b"bop smget 5 2 0..10 3\r\na,b\r\n"), Expect::Elements(Shape::Sorted));
+        assert_eq!(Expect::of(b"bop mget 5 2 0..10\r\na,b\r\n"), Expect::Collections);
+    }
+
+    /* Reads one reply off `input`, and returns it with what is left. */
+    fn read_one(input: &[u8], expect: Expect) -> (String, String) {
+        let mut rbuf = input;
+        let reply = read(&mut rbuf, expect).unwrap();
+        return (String::from_utf8_lossy(&reply).to_string(), String::from_utf8_lossy(rbuf).to_string());
+    }
+
+    #[test]
+    fn reads_values_by_byte_count() {
+        let input = b"VALUE a 0 8\r\nx\r\nEND\r\n\r\nVALUE b 1 1 42\r\ny\r\nEND\r\nNEXT\r\n";
+        let (reply, rest) = read_one(input, Expect::Values);
+        assert_eq!(rest, "NEXT\r\n");
+        assert!(reply.ends_with("42\r\ny\r\nEND\r\n"));
+        assert_eq!(read_one(b"END\r\nNEXT\r\n", Expect::Values), ("END\r\n".to_string(), "NEXT\r\n".to_string()));
+    }
+
+    #[test]
+    fn reads_elements_by_byte_count() {
+        let lop = "VALUE 0 2\r\n8 x\r\nEND\r\n\r\n3 abc\r\nDELETED\r\n";
+        assert_eq!(read_one((lop.to_string() + "NEXT").as_bytes(), Expect::Elements(Shape::List)),
+                   (lop.to_string(), "NEXT".to_string()));
+        let mop = "VALUE 0 1\r\nf1 5 a b c\r\nEND\r\n";
+        assert_eq!(read_one((mop.to_string() + "NEXT").as_bytes(), Expect::Elements(Shape::Map)).0, mop);
+        let bop = "VALUE 0 2\r\n0x01 0xff 5 END\r\n\r\n2 3 abc\r\nTRIMMED\r\n";
+        assert_eq!(read_one((bop.to_string() + "NEXT").as_bytes(), Expect::Elements(Shape::Btree)).0, bop);
+        let pwg = "VALUE 3 0 1 0\r\n10 2 ab\r\nEND\r\n";
+        assert_eq!(read_one((pwg.to_string() + "NEXT").as_bytes(), Expect::Elements(Shape::Btree)).0, pwg);
+        assert_eq!(read_one(b"NOT_FOUND\r\nNEXT", Expect::Elements(Shape::List)).0, "NOT_FOUND\r\n");
+    }
+
+    #[test]
+    fn reads_sorted_elements_and_keys() {
+        let smget = "ELEMENTS 2\r\na 0 1 5 END\r\n\r\nb 0 2 0x0f 1 x\r\n\
+                     MISSED_KEYS 2\r\nc\r\nd OUT_OF_RANGE\r\nTRIMMED_KEYS 1\r\ne 9\r\nDUPLICATED_TRIMMED\r\n";
+        assert_eq!(read_one((smget.to_string() + "NEXT").as_bytes(), Expect::Elements(Shape::Sorted)),
+                   (smget.to_string(), "NEXT".to_string()));
+        let old = "VALUE 1\r\na 0 1 1 x\r\nMISSED_KEYS 0\r\nEND\r\n";
+        assert_eq!(read_one((old.to_string() + "NEXT").as_bytes(), Expect::Elements(Shape::Sorted)).0, old);
+    }
+
+    #[test]
+    fn reads_collections_per_key() {
+        let mget = "VALUE a OK 0 2\r\nELEMENT 1 8 x\r\nEND\r\n\r\nELEMENT 2 0x01 1 y\r\n\
+                    VALUE b NOT_FOUND\r\nVALUE c TRIMMED 0 0\r\nEND\r\n";
+        assert_eq!(read_one((mget.to_string() + "NEXT").as_bytes(), Expect::Collections),
+                   (mget.to_string(), "NEXT".to_string()));
+    }
+
+    #[test]
+    fn rejects_malformed_element_lines() {
+        let mut rbuf: &[u8] = b"VALUE 0 1\r\nx abc\r\nEND\r\n";
+        assert_eq!(read(&mut rbuf, Expect::Elements(Shape::List)).unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut rbuf: &[u8] = b"VALUE a OK 0 1\r\n1 1 x\r\nEND\r\n";
+        assert_eq!(read(&mut rbuf, Expect::Collections).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_status_replies() {
+        let attr = "ATTR flags=0\r\nATTR count=1\r\nEND\r\n";
+        assert_eq!(read_one((attr.to_string() + "NEXT").as_bytes(), Expect::Status).0, attr);
+        let sasl = "SASL_CONTINUE\r\nchallenge\r\n";
+        assert_eq!(read_one((sasl.to_string() + "NEXT").as_bytes(), Expect::Status).0, sasl);
+        let pipe = "RESPONSE 2\r\nSTORED\r\nSTORED\r\nEND\r\n";
+        assert_eq!(read_one((pipe.to_string() + "NEXT").as_bytes(), Expect::Status).0, pipe);
+        let failed = "RESPONSE 3\r\nSTORED\r\nNOT_FOUND\r\nPIPE_ERROR bad error\r\n";
+        assert_eq!(read_one((failed.to_string() + "NEXT").as_bytes(), Expect::Status).0, failed);
+        assert_eq!(read_one(b"STORED\r\nNEXT", Expect::Status).0, "STORED\r\n");
+        assert_eq!(read_one(b"CLIENT_ERROR bad data chunk\r\nNEXT", Expect::Status).0, "CLIENT_ERROR bad data chunk\r\n");
+    }
+
+    #[test]
+    fn reads_listings_to_end_unless_single() {
+        let stats = "STAT pid 1\r\nSTAT uptime 2\r\nEND\r\n";
+        assert_eq!(read_one((stats.to_string() + "NEXT").as_bytes(), Expect::Listing).0, stats);
+        let config = "maxconns 3000\r\nEND\r\n";
+        assert_eq!(read_one((config.to_string() + "NEXT").as_bytes(), Expect::Listing).0, config);
+        assert_eq!(read_one(b"NOT_SUPPORTED\r\nNEXT", Expect::Listing).0, "NOT_SUPPORTED\r\n");
+        assert_eq!(read_one(b"ERROR unknown command\r\nNEXT", Expect::Listing).0, "ERROR unknown command\r\n");
+    }
+}
@@ -0,0 +1,323 @@
+use std::io::{self, BufReader, ErrorKind};
+use std::io::prelude::*;
+use std::net::Shutdown;
+use std::thread;
+use std::time::Duration;
+use rsasl::prelude::*;
+use super::credentials::Credentials;
+use super::error::{ConnectError, Error, ProtocolError};
+use super::event::{Event, Log};
+use super::reply::{self, Expect};
+
+/// Socket timeouts; `None` waits for as long as it takes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub write: Option<Duration>,
+}
+
+/// A connected byte stream the ASCII protocol can run over.
+pub trait Stream: Read + Write + Sized {
+    /// Transport specific connection settings, such as TLS for TCP.
+    type Options: Default;
+
+    /// Connects to `address`; the read and write timeouts are in force from
+    /// the start, so a handshake cannot hang either.
+    fn open(address: &str, options: &Self::Options, timeouts: &Timeouts) -> Result<Self, ConnectError>;
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+/// How hard to try to get a dropped connection back.
+#[derive(Clone, Copy, Debug)]
+pub struct Reconnect {
+    /// Attempts after the first one fails; 0 gives up at once.
+    pub attempts: u32,
+    /// Wait before the first retry; it doubles with every retry, up to `MAX_BACKOFF`.
+    pub backoff: Duration,
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/* Request bytes a pipeline lets await their replies. The server stops reading
+   while its replies go unread, so writing every request first could leave
+   both sides stuck on full socket buffers. */
+const WINDOW: usize = 64 * 1024;
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Reconnect { attempts: 3, backoff: Duration::from_millis(100) }
+    }
+}
+
+/* Read-only requests, which are safe to send again when their reply was lost. */
+fn replayable(req: &[u8]) -> bool {
+    let line = String::from_utf8_lossy(req.split(|&b| b == b'\n').next().unwrap_or(b""));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    return match words.as_slice() {
+        ["get" | "gets" | "mget" | "mgets" | "getattr" | "stats" | "version" | "scan" | "help", ..] => true,
+        ["lop" | "sop" | "mop" | "bop", "get", ..] => !words.contains(&"delete") && !words.contains(&"drop"),
+        ["sop", "exist", ..] | ["bop", "count" | "mget" | "smget" | "position" | "gbp" | "pwg", ..] => true,
+        _ => false,
+    };
+}
+
+fn not_connected(address: &str) -> io::Error {
+    io::Error::new(ErrorKind::NotConnected, format!("not connected to {}", address))
+}
+
+/// Request/reply client shared by every stream transport.
+pub struct StreamClient<S: Stream> {
+    pub auth: Option<Credentials>,
+    pub options: S::Options,
+    pub retry: Reconnect,
+    pub timeouts: Timeouts,
+    pub log: Option<Log>,
+    conn: Option<S>,
+    rbuf: Option<BufReader<S>>,
+}
+
+impl<S: Stream> Default for StreamClient<S> {
+    fn default() -> Self {
+        StreamClient {
+            auth: None, options: Default::default(), retry: Default::default(), timeouts: Default::default(),
+            log: None, conn: None, rbuf: None,
+        }
+    }
+}
+
+impl<S: Stream> StreamClient<S> {
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(log) = self.log.as_ref() {
+            log(&event);
+        }
+    }
+
+    /// Opens the connection, retrying with backoff, and signs in again if SASL
+    /// is on. Only connections the server refused are retried.
+    pub fn connect(&mut self, address: &str) -> Result<(), Error> {
+        self.close();
+        let mut backoff = self.retry.backoff;
+        for attempt in 0.. {
+            if attempt > 0 {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            match S::open(address, &self.options, &self.timeouts) {
+                Ok(stream) => {
+                    self.rbuf = Some(BufReader::new(stream.try_clone()?));
+                    self.conn = Some(stream);
+                    if attempt > 0 {
+                        self.emit(Event::Reconnected { address: address.to_string() });
+                    }
+                    if let Some(creds) = self.auth.clone() {
+                        if let Err(err) = self.authenticate(address, creds) {
+                            self.close();
+                            return Err(match err {
+                                Error::Io(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                                    ConnectError::Timeout(address.to_string(), "in the SASL exchange", self.timeouts.read).into(),
+                                err => err,
+                            });
+                        }
+                    }
+                    return Ok(());
+                },
+                Err(ConnectError::Refused(_, error)) if attempt < self.retry.attempts =>
+                    self.emit(Event::Retrying { address: address.to_string(), error, backoff }),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        unreachable!();
+    }
+
+    fn close(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            let _ = conn.shutdown(Shutdown::Both);
+        }
+        self.rbuf = None;
+    }
+
+    /// Sends one request and reads its reply, reconnecting when the connection
+    /// is down. A request the server never got is sent again; one whose reply
+    /// was lost is sent again only if it is read-only, and reported otherwise.
+    pub fn request(&mut self, address: &str, buf: &[u8], expect: Expect) -> Result<Vec<u8>, Error> {
+        if !self.is_connected() {
+            self.connect(address)?;
+        }
+        if self.write(buf) {
+            self.connect(address)?;
+            if self.write(buf) {
+                return Err(Error::Io(not_connected(address)));
+            }
+        }
+        return match self.read(expect) {
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                Err(self.timed_out(address, buf)),
+            Err(err) if !self.is_connected() && err.kind() != ErrorKind::InvalidData => {
+                let line = String::from_utf8_lossy(buf.split(|&b| b == b'\r').next().unwrap_or(b"")).to_string();
+                if !replayable(buf) {
+                    return Err(Error::Io(io::Error::new(err.kind(), format!(
+                        "connection lost before the reply to \"{}\" ({}); it may or may not have been applied", line, err))));
+                }
+                self.connect(address)?;
+                if self.write(buf) {
+                    return Err(Error::Io(not_connected(address)));
+                }
+                self.emit(Event::Replaying { address: address.to_string(), request: line });
+                Ok(self.read(expect)?)
+            },
+            res => Ok(res?),
+        };
+    }
+
+    /// Writes the requests back-to-back and returns their replies in order.
+    /// Replies are read as they come once `WINDOW` bytes of requests await
+    /// theirs. Nothing is resent: once the connection fails, requests that
+    /// went out without a reply are reported as lost, and the rest as unsent.
+    pub fn pipeline(&mut self, address: &str, reqs: &[Vec<u8>]) -> Vec<Result<Vec<u8>, Error>> {
+        if !self.is_connected() {
+            if let Err(err) = self.connect(address) {
+                let mut res = vec![Err(err)];
+                res.extend(reqs.iter().skip(1).map(|_| Err(Error::Io(not_connected(address)))));
+                return res;
+            }
+        }
+        let mut res = Vec::with_capacity(reqs.len());
+        let (mut sent, mut in_flight, mut writing) = (0, 0, true);
+        while res.len() < reqs.len() {
+            if writing && sent < reqs.len() && (sent == res.len() || in_flight + reqs[sent].len() <= WINDOW) {
+                writing = !self.write(&reqs[sent]);
+                if writing {
+                    in_flight += reqs[sent].len();
+                    sent += 1;
+                }
+                continue;
+            }
+            let req = &reqs[res.len()];
+            if res.len() >= sent {
+                res.push(Err(Error::Io(not_connected(address))));
+                continue;
+            }
+            in_flight -= req.len();
+            if !self.is_connected() {
+                let line = String::from_utf8_lossy(req.split(|&b| b == b'\r').next().unwrap_or(b"")).to_string();
+                res.push(Err(Error::Io(io::Error::new(ErrorKind::NotConnected, format!(
+                    "connection lost before the reply to \"{}\"; it may or may not have been applied", line)))));
+                continue;
+            }
+            res.push(match self.read(Expect::of(req)) {
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                    Err(self.timed_out(address, req)),
+                res => res.map_err(Error::from),
+            });
+        }
+        return res;
+    }
+
+    /* A late reply would be taken for the next one's, so the connection is gone too. */
+    fn timed_out(&self, address: &str, buf: &[u8]) -> Error {
+        let line = String::from_utf8_lossy(buf.split(|&b| b == b'\r').next().unwrap_or(b"")).to_string();
+        return Error::Io(io::Error::new(ErrorKind::TimedOut, format!(
+            "no reply to \"{}\" within {:?}; dropped the connection to {}",
+            line, self.timeouts.read.unwrap_or_default(), address)));
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> bool {
+        let res = match self.conn.as_mut() {
+            None => return true,
+            Some(conn) => conn.write_all(buf),
+        };
+        if res.is_err() {
+            self.close();
+        }
+        return res.is_err();
+    }
+
+    pub fn read(&mut self, expect: Expect) -> io::Result<Vec<u8>> {
+        let rbuf = match self.rbuf.as_mut() {
+            Some(rbuf) => rbuf,
+            None => return Ok(Vec::new()),
+        };
+        let res = reply::read(rbuf, expect);
+        if res.is_err() {
+            self.close();
+        }
+        return res;
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        let rbuf = self.rbuf.as_mut().ok_or_else(|| io::Error::from(ErrorKind::NotConnected))?;
+        if rbuf.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed by server"));
+        }
+        return Ok(line);
+    }
+
+    fn authenticate(&mut self, address: &str, creds: Credentials) -> Result<(), Error> {
+        let failed = |reason: String| Error::Connect(ConnectError::Auth(address.to_string(), reason));
+        let username = creds.username.clone();
+        let config = SASLConfig::with_credentials(creds.authzid, creds.username, creds.password)
+                                .map_err(|e| failed(e.to_string()))?;
+        let client = SASLClient::new(config);
+
+        if self.write(b"sasl mech\r\n") {
+            return Err(Error::Io(not_connected(address)));
+        }
+        let mut line = self.read_line()?;
+        let mech_list = match line.strip_prefix("SASL_MECH ") {
+            Some(list) => list.trim_end().to_string(),
+            None => return Err(ProtocolError(format!("expected SASL_MECH, got \"{}\"", line.trim_end())).into()),
+        };
+        let server_mech: Vec<&Mechname> = mech_list.split_whitespace()
+                                                   .filter(|s| creds.mech.as_ref().is_none_or(|m| s.eq_ignore_ascii_case(m)))
+                                                   .filter_map(|s| Mechname::parse(s.as_bytes()).ok())
+                                                   .collect();
+        if let (Some(want), true) = (creds.mech.as_ref(), server_mech.is_empty()) {
+            return Err(failed(format!("{} not offered by server (SASL_MECH {})", want, mech_list)));
+        }
+        let mut session = client.start_suggested(&server_mech).map_err(|e| failed(e.to_string()))?;
+
+        let mut mech = Some(session.get_mechname().to_string() + " ");
+        let mut resp: Option<Vec<u8>> = None;
+        loop {
+            let mut out = Vec::new();
+            let state = session.step(resp.as_deref(), &mut out).map_err(|e| failed(e.to_string()))?;
+            if !state.is_running() && !state.is_finished() {
+                return Err(failed("SASL not finished".to_string()));
+            }
+            let out_str = std::str::from_utf8(&out).map_err(|e| ProtocolError(e.to_string()))?;
+
+            let req = format!("sasl auth {}{}\r\n{}\r\n", mech.clone().unwrap_or("".to_string()),
+                                                                  out_str.len(), out_str);
+            if self.write(req.as_bytes()) {
+                return Err(Error::Io(not_connected(address)));
+            }
+            match self.read_line()?.as_str() {
+                s if s.starts_with("SASL_CONTINUE") => {
+                    line = self.read_line()?;
+                    resp = Some(line.strip_suffix("\r\n").unwrap_or(&line).as_bytes().to_vec());
+                    mech = None;
+                },
+                "SASL_OK\r\n" => {
+                    self.emit(Event::Authenticated {
+                        address: address.to_string(), user: username, mech: session.get_mechname().to_string(),
+                    });
+                    return Ok(());
+                },
+                s => return Err(failed(s.trim_end().to_string())),
+            }
+        }
+    }
+}
+
+impl<S: Stream> Drop for StreamClient<S> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
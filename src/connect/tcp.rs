@@ -1,177 +1,94 @@
-use std::thread;
-use std::io::{self, Write, BufReader, ErrorKind};
-use std::io::prelude::*;
+use std::io::{self, Read, Write, ErrorKind};
 use std::net::{TcpStream, Shutdown, ToSocketAddrs};
-use std::thread::JoinHandle;
-use rsasl::prelude::*;
-
-#[derive(Default)]
-pub struct TcpClient {
-    pub auth: bool,
-    conn: Option<TcpStream>,
-    hand: Option<JoinHandle<()>>,
-    runn: bool,
+use std::sync::Arc;
+use rustls::ClientConfig;
+use super::error::ConnectError;
+use super::stream::{self, StreamClient, Timeouts};
+use super::tls::TlsStream;
+
+/// A TCP connection, in the clear or wrapped in TLS.
+pub enum Socket {
+    Plain(TcpStream),
+    Tls(TlsStream),
 }
 
-impl TcpClient {
-    pub fn connect(&mut self, address: &str) {
-        if !self.conn.is_none() { drop(self.conn.take().unwrap()); }
-        self.runn = false;
-        let addrs_iter = match address.to_socket_addrs() {
-            Ok(addrs) => addrs.collect::<Vec<_>>(),
-            Err(err) => {
-                eprintln!("ERROR: {}", err);
-                std::process::exit(1);
-            }
-        };
-
-        let mut last_err = None;
-        for addr in addrs_iter {
-            match TcpStream::connect(addr) {
-                Ok(stream) => {
-                    self.conn = Some(stream.try_clone().unwrap());
-                    if self.auth {
-                        self.authenticate(stream.try_clone().unwrap());
-                    }
-
-                    self.hand = self.activate_reader(stream);
-                    self.runn = true;
-                    return;
+impl stream::Stream for Socket {
+    type Options = Option<Arc<ClientConfig>>;
+
+    fn open(address: &str, tls: &Self::Options, timeouts: &Timeouts) -> Result<Socket, ConnectError> {
+        let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+        let addrs = address.to_socket_addrs().map_err(|err| ConnectError::Resolve(address.to_string(), err))?;
+        let mut last_err = ConnectError::Refused(address.to_string(), io::Error::new(ErrorKind::NotFound, "no address"));
+        for addr in addrs {
+            let sock = match timeouts.connect {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            let sock = sock.and_then(|sock| {
+                sock.set_read_timeout(timeouts.read)?;
+                sock.set_write_timeout(timeouts.write)?;
+                Ok(sock)
+            });
+            let sock = match sock {
+                Ok(sock) => sock,
+                Err(err) => {
+                    last_err = ConnectError::failed(address, err, "connecting", timeouts.connect);
+                    continue;
                 },
-                Err(e) => last_err = Some(e)
+            };
+            let config = match tls.as_ref() {
+                Some(config) => config.clone(),
+                None => return Ok(Socket::Plain(sock)),
+            };
+            match TlsStream::connect(config, host, sock) {
+                Ok(sock) => return Ok(Socket::Tls(sock)),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                    last_err = ConnectError::Timeout(address.to_string(), "in the TLS handshake", timeouts.read),
+                Err(err) => last_err = ConnectError::Tls(address.to_string(), err),
             }
-        };
-
-        let err = last_err.unwrap();
-        eprintln!("ERROR: {}", err);
-        if err.kind() != ErrorKind::ConnectionRefused {
-            std::process::exit(1);
         }
+        return Err(last_err);
     }
 
-    pub fn write(&mut self, line: String) -> bool {
-        return match self.conn.as_mut() {
-            None => true,
-            Some(conn) => matches!(
-                conn.write(line.as_bytes()),
-                Err(ref err) if err.kind() == ErrorKind::BrokenPipe
-            ),
-        };
+    fn try_clone(&self) -> io::Result<Socket> {
+        Ok(match self {
+            Socket::Plain(sock) => Socket::Plain(sock.try_clone()?),
+            Socket::Tls(sock) => Socket::Tls(sock.try_clone()?),
+        })
     }
 
-    fn read_line(&mut self, rbuf: &mut BufReader<TcpStream>) -> String {
-        let mut line = String::new();
-        match rbuf.read_line(&mut line) {
-            Err(e) => eprintln!("ERROR: {}", e),
-            _ => ()
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Socket::Plain(sock) => sock.shutdown(how),
+            Socket::Tls(sock) => sock.shutdown(how),
         }
-        return line;
     }
+}
 
-    fn authenticate(&mut self, conn: TcpStream) {
-        let mut rbuf = BufReader::new(conn);
-        let mut username = String::new();
-        print!("username: ");
-        io::stdout().flush().unwrap();
-        io::stdin().read_line(&mut username).unwrap();
-        let username = username.trim().to_string();
-        let password = rpassword::prompt_password("password: ").unwrap();
-
-        let config = SASLConfig::with_credentials(None, username, password).unwrap();
-        let client = SASLClient::new(config);
-
-        self.write("sasl mech\r\n".to_string());
-        let mut line = self.read_line(&mut rbuf);
-
-        if !line.starts_with("SASL_MECH ") {
-            eprintln!("AUTH_ERROR: Protocol error");
-            return;
-        }
-
-        let mech_list: &str = &line["SASL_MECH ".len()..line.len() - "\r\n".len()];
-        let server_mech: Vec<&Mechname> = mech_list.split_whitespace()
-                                                   .filter_map(|s| Mechname::parse(s.as_bytes()).ok())
-                                                   .collect();
-        let mut session = match client.start_suggested(&server_mech) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("AUTH_ERROR: {}", e);
-                return;
-            }
-        };
-
-        let mut mech = Some(session.get_mechname().to_string() + " ");
-        let mut resp: Option<Vec<u8>> = None;
-        loop {
-            let mut out = Vec::new();
-            let state = match session.step(resp.as_deref(), &mut out) {
-                Ok(res) => res,
-                Err(e) => {
-                    eprintln!("AUTH_ERROR: {}", e);
-                    break;
-                }
-            };
-
-            if !state.is_running() {
-                if state.is_finished() {
-                } else {
-                    eprintln!("AUTH_ERROR: SASL not finished");
-                    break;
-                }
-            }
-
-            let out_str = match std::str::from_utf8(&out) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("ERROR: {}", e);
-                    break;
-                }
-            };
-
-            let req = format!("sasl auth {}{}\r\n{}\r\n", mech.clone().unwrap_or("".to_string()),
-                                                                  out_str.len(), out_str);
-            self.write(req);
-            match self.read_line(&mut rbuf).as_str() {
-                s if s.starts_with("SASL_CONTINUE") => {
-                    line = self.read_line(&mut rbuf);
-                    resp = Some(line.strip_suffix("\r\n").unwrap_or(&line).as_bytes().to_vec());
-                    mech = None;
-                },
-                "SASL_OK\r\n" => break,
-                _ => {
-                    eprintln!("AUTH_ERROR: SASL authenticate failed");
-                    break;
-                }
-            }
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Plain(sock) => sock.read(buf),
+            Socket::Tls(sock) => sock.read(buf),
         }
     }
+}
 
-    fn activate_reader(&mut self, sock: TcpStream) -> Option<JoinHandle<()>> {
-        let mut rbuf = BufReader::new(sock);
-        let mut line = String::new();
-        return Some(thread::spawn(move || {
-            loop {
-                match rbuf.read_line(&mut line) {
-                    Err(err) => eprintln!("ERROR: {}", err),
-                    Ok(0) => break,
-                    _ => ()
-                }
-                print!("{}", line);
-                line.clear();
-            }
-        }));
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Plain(sock) => sock.write(buf),
+            Socket::Tls(sock) => sock.write(buf),
+        }
     }
-}
 
-impl Drop for TcpClient {
-    fn drop(&mut self) {
-        if self.runn {
-            if let Some(conn) = self.conn.as_mut() {
-                let _ = conn.shutdown(Shutdown::Write);
-            }
-            if let Some(handle) = self.hand.take() {
-                let _ = handle.join();
-            }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Plain(sock) => sock.flush(),
+            Socket::Tls(sock) => sock.flush(),
         }
     }
 }
+
+/// Client for memcached/ARCUS over TCP, optionally with TLS.
+pub type TcpClient = StreamClient<Socket>;
@@ -0,0 +1,124 @@
+use std::io::{self, Read, Write, ErrorKind};
+use std::net::{Shutdown, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+
+fn tls_error(err: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("TLS: {}", err))
+}
+
+/* Accepts any server certificate, for --insecure. Signatures are still checked. */
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>],
+                          _server_name: &ServerName<'_>, _ocsp: &[u8], _now: UnixTime)
+                          -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Builds the client side TLS settings shared by every connection.
+pub fn config(cacert: Option<&Path>, cert: Option<&Path>, key: Option<&Path>,
+              insecure: bool) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+                               .with_safe_default_protocol_versions()
+                               .map_err(tls_error)?;
+
+    let builder = if insecure {
+        builder.dangerous().with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+    } else {
+        let mut roots = RootCertStore::empty();
+        match cacert {
+            Some(path) => {
+                for cert in CertificateDer::pem_file_iter(path).map_err(tls_error)? {
+                    roots.add(cert.map_err(tls_error)?).map_err(tls_error)?;
+                }
+            },
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        builder.with_root_certificates(roots)
+    };
+
+    let config = match (cert, key) {
+        (Some(cert), Some(key)) => {
+            let chain = CertificateDer::pem_file_iter(cert).map_err(tls_error)?
+                                                           .collect::<Result<Vec<_>, _>>()
+                                                           .map_err(tls_error)?;
+            let key = PrivateKeyDer::from_pem_file(key).map_err(tls_error)?;
+            builder.with_client_auth_cert(chain, key).map_err(tls_error)?
+        },
+        _ => builder.with_no_client_auth(),
+    };
+    return Ok(Arc::new(config));
+}
+
+/// A TLS session over TCP. Clones share the one session behind a lock, so a
+/// read and a write never overlap: use them one at a time, as the request
+/// loop does with its reader and writer.
+pub struct TlsStream(Arc<Mutex<StreamOwned<ClientConnection, TcpStream>>>);
+
+impl TlsStream {
+    /// Runs the TLS handshake with the server at `host` over `sock`.
+    pub fn connect(config: Arc<ClientConfig>, host: &str, mut sock: TcpStream) -> io::Result<TlsStream> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let name = ServerName::try_from(host.to_string()).map_err(tls_error)?;
+        let mut conn = ClientConnection::new(config, name).map_err(tls_error)?;
+        while conn.is_handshaking() {
+            /* Timeouts keep their kind, so the caller can tell them apart. */
+            conn.complete_io(&mut sock).map_err(|err| match err.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => err,
+                _ => tls_error(err),
+            })?;
+        }
+        return Ok(TlsStream(Arc::new(Mutex::new(StreamOwned::new(conn, sock)))));
+    }
+
+    pub fn try_clone(&self) -> io::Result<TlsStream> {
+        Ok(TlsStream(self.0.clone()))
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let mut stream = self.0.lock().unwrap();
+        stream.conn.send_close_notify();
+        let _ = stream.flush();
+        stream.sock.shutdown(how)
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
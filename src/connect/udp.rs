@@ -1,137 +1,359 @@
-use std::io;
-use std::time::Duration;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::time::{Duration, Instant};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use super::credentials::Credentials;
+use super::error::{ConnectError, Error};
+use super::event::{Event, Log};
+use super::reply::{self, Expect};
 
 const MTU: usize = 1400;
+const HEADER: usize = 8;
+
+/// The 8 byte frame header memcached puts in front of every UDP datagram:
+/// request id, sequence number, total datagrams and a reserved field, each a
+/// big-endian u16.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Header {
+    pub rqid: u16,
+    pub seq: u16,
+    pub total: u16,
+    pub reserved: u16,
+}
+
+impl Header {
+    pub fn encode(&self) -> [u8; HEADER] {
+        let mut buf = [0; HEADER];
+        buf[0..2].copy_from_slice(&self.rqid.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.seq.to_be_bytes());
+        buf[4..6].copy_from_slice(&self.total.to_be_bytes());
+        buf[6..8].copy_from_slice(&self.reserved.to_be_bytes());
+        return buf;
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Header> {
+        if buf.len() < HEADER { return None; }
+        let field = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+        return Some(Header { rqid: field(0), seq: field(2), total: field(4), reserved: field(6) });
+    }
+}
+
+/// How requests and replies are laid out in datagrams.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Framing {
+    /// memcached's frame header in front of every datagram.
+    #[default]
+    Header,
+    /// Bare protocol bytes; a reply ends once it reads as complete.
+    Raw,
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { Framing::Header => "header", Framing::Raw => "raw" })
+    }
+}
+
+/// Splits a request into framed datagrams of at most `MTU` payload bytes each.
+pub fn frames(rqid: u16, line: &[u8]) -> Vec<Vec<u8>> {
+    let chunks: Vec<&[u8]> = if line.is_empty() { vec![line] } else { line.chunks(MTU).collect() };
+    let total = chunks.len() as u16;
+    return chunks.iter().enumerate().map(|(seq, chunk)| {
+        let header = Header { rqid, seq: seq as u16, total, reserved: 0 };
+        [&header.encode()[..], chunk].concat()
+    }).collect();
+}
+
+/// Collects the datagrams of one reply, whatever order they arrive in.
+#[derive(Default)]
+pub struct Reassembly {
+    rqid: u16,
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+impl Reassembly {
+    pub fn new(rqid: u16) -> Reassembly {
+        Reassembly { rqid, parts: Vec::new() }
+    }
+
+    /// Takes in one datagram. Datagrams of other requests and duplicates are
+    /// ignored; a datagram that disagrees on the total count is an error.
+    pub fn add(&mut self, datagram: &[u8]) -> io::Result<()> {
+        let header = match Header::decode(datagram) {
+            Some(header) => header,
+            None => return Err(io::Error::new(ErrorKind::InvalidData, "short UDP datagram (try --udp-framing probe)")),
+        };
+        if header.rqid != self.rqid { return Ok(()); }
+        if header.total == 0 || header.seq >= header.total
+            || (!self.parts.is_empty() && self.parts.len() != header.total as usize) {
+            return Err(io::Error::new(ErrorKind::InvalidData,
+                                      format!("invalid UDP header: seq {} of {}", header.seq, header.total)));
+        }
+        if self.parts.is_empty() {
+            self.parts = vec![None; header.total as usize];
+        }
+        let part = &mut self.parts[header.seq as usize];
+        if part.is_none() {
+            *part = Some(datagram[HEADER..].to_vec());
+        }
+        return Ok(());
+    }
+
+    pub fn is_complete(&self) -> bool {
+        !self.parts.is_empty() && self.parts.iter().all(Option::is_some)
+    }
+
+    /// Sequence numbers not received yet.
+    pub fn missing(&self) -> Vec<usize> {
+        self.parts.iter().enumerate().filter(|(_, p)| p.is_none()).map(|(i, _)| i).collect()
+    }
+
+    pub fn into_reply(self) -> Vec<u8> {
+        self.parts.into_iter().flatten().flatten().collect()
+    }
+}
 
 #[derive(Default)]
 pub struct UdpClient {
+    /// Request id of the next request; it goes up by one with every request.
     pub rqid: u16,
+    /// Reply timeout in milliseconds.
     pub time: u64,
-    addr: Option<SocketAddr>,
+    pub framing: Framing,
+    /// Local address to send from; any address of the server's family if unset.
+    pub bind: Option<SocketAddr>,
+    addrs: Vec<SocketAddr>,
+    at: usize,
     conn: Option<UdpSocket>,
-    sync: bool,
-    pub auth: bool,
+    resp: Option<io::Result<Vec<u8>>>,
+    pub auth: Option<Credentials>,
+    pub log: Option<Log>,
 }
 
 impl UdpClient {
-    pub fn connect(&mut self, address: &str) {
-        let addrs_iter = match address.to_socket_addrs() {
-            Ok(addrs) => addrs.collect::<Vec<_>>(),
-            Err(err) => {
-                eprintln!("ERROR: {}", err);
-                std::process::exit(1);
-            }
-        };
+    pub fn connect(&mut self, address: &str) -> Result<(), ConnectError> {
+        self.addrs = address.to_socket_addrs()
+                            .map_err(|err| ConnectError::Resolve(address.to_string(), err))?
+                            .collect();
+        self.at = 0;
+        if !self.open() {
+            let err = io::Error::new(ErrorKind::AddrNotAvailable, "no usable address");
+            return Err(ConnectError::Refused(address.to_string(), err));
+        }
+        return Ok(());
+    }
 
-        let timeout = Some(Duration::from_millis(self.time));
-        match UdpSocket::bind("127.0.0.1:0") {
-            Ok(sock) => {
-                match sock.set_read_timeout(timeout) {
-                    Err(err) => {
-                        eprintln!("ERROR: {}", err);
-                        std::process::exit(1);
-                    }
-                    _ => ()
-                }
-                self.conn = Some(sock);
-            }
-            Err(err) => eprintln!("ERROR: {}", err)
+    /// Sends one request and waits for its reply, opening the socket first if need be.
+    pub fn request(&mut self, address: &str, buf: &[u8], expect: Expect) -> Result<Vec<u8>, Error> {
+        if !self.is_connected() {
+            self.connect(address)?;
+        }
+        self.write(buf);
+        return match self.read(expect) {
+            Err(err) if !self.is_connected() => {
+                let limit = Duration::from_millis(self.time.max(1));
+                Err(ConnectError::failed(address, err, "waiting for a reply", Some(limit)).into())
+            },
+            res => Ok(res?),
         };
-        for addr in addrs_iter {
-            self.addr = Some(addr);
-            break; /* TODO */
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(log) = self.log.as_ref() {
+            log(&event);
         }
     }
 
-    pub fn write(&mut self, line: String) -> bool {
-        if self.addr.is_none() { return true }
-        let msg = if self.sync { self.split_message(line) }
-                      else { self.build_header(line) };
-        let mut buf = [0; MTU];
-        for m in msg {
-            match self.conn.as_mut().unwrap().send_to(&m, self.addr.unwrap()) {
-                Err(err) => { eprintln!("ERROR: {}", err); break; }
-                _ => ()
+    /* Binds a socket to the first address from `self.at` on that takes one. */
+    fn open(&mut self) -> bool {
+        self.conn = None;
+        while let Some(&addr) = self.addrs.get(self.at) {
+            let local = self.bind.unwrap_or(match addr {
+                SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            });
+            match UdpSocket::bind(local).and_then(|sock| sock.connect(addr).map(|_| sock)) {
+                Ok(sock) => {
+                    self.conn = Some(sock);
+                    return true;
+                },
+                Err(error) => self.emit(Event::Unusable { address: addr, error }),
             }
-            match self.conn.as_mut().unwrap().recv_from(&mut buf) {
-                Err(err) => {
-                    if err.kind() == io::ErrorKind::WouldBlock {
-                        self.sync = !self.sync;
+            self.at += 1;
+        }
+        return false;
+    }
+
+    pub fn write(&mut self, line: &[u8]) -> bool {
+        if self.conn.is_none() { return true }
+        loop {
+            match self.exchange(self.framing, line) {
+                Err(err) if matches!(err.kind(), ErrorKind::ConnectionRefused | ErrorKind::NetworkUnreachable
+                                                     | ErrorKind::HostUnreachable | ErrorKind::AddrNotAvailable) => {
+                    /* Try the request again on the next address, if there is one. */
+                    let kind = err.kind();
+                    self.emit(Event::Unusable { address: self.addrs[self.at], error: err });
+                    self.at += 1;
+                    if !self.open() {
+                        self.resp = Some(Err(io::Error::new(kind, "no resolved address answered")));
+                        return false;
                     }
-                    else { eprintln!("ERROR: {}", err); break; }
+                },
+                res => {
+                    self.resp = res.transpose();
+                    return false;
                 }
-                _ => { self.sync = false; break; }
             }
         }
-        if !self.sync {
-            let mut hdr = self.parse_header(&buf[0..8]);
-            if hdr[0] as u16 != self.rqid {
-                eprintln!("ERROR: Invalid header");
-                return false;
-            }
-            let mut data: Vec<Vec<u8>> = vec![vec![]; hdr[2]];
-            buf[8..].clone_into(data[hdr[1]].as_mut());
-            for _ in 1..hdr[2] {
-                buf = [0; MTU];
-                match self.conn.as_mut().unwrap().recv_from(&mut buf) {
-                    Ok(_) => {
-                        hdr = self.parse_header(&buf[0..8]);
-                        if hdr[0] as u16 != self.rqid {
-                            eprintln!("ERROR: Invalid header");
-                            return false;
-                        }
-                        buf[8..].clone_into(data[hdr[1]].as_mut());
-                    }
-                    _ => ()
+    }
+
+    /// Finds out which framing the server expects by sending `version` in
+    /// each, and keeps the one that gets an answer.
+    pub fn probe(&mut self) -> io::Result<Framing> {
+        if self.conn.is_none() {
+            return Err(io::Error::new(ErrorKind::NotConnected, "not connected"));
+        }
+        for framing in [Framing::Header, Framing::Raw] {
+            if let Ok(Some(reply)) = self.exchange(framing, b"version\r\n") {
+                if reply.starts_with(b"VERSION ") {
+                    self.framing = framing;
+                    return Ok(framing);
                 }
             }
-            let flat: Vec<u8> = data.iter()
-                                    .flat_map(|arr| arr.iter())
-                                    .cloned()
-                                    .collect();
-            match String::from_utf8(flat) {
-                Err(err) => eprintln!("ERROR: {}", err),
-                Ok(msg) => print!("{}", msg)
-            };
         }
-        return false;
+        return Err(io::Error::new(ErrorKind::TimedOut, "no answer to version with either UDP framing"));
     }
 
-    fn parse_header(&mut self, head: &[u8]) -> [usize; 4] {
-        let mut cvt: [usize; 4] = [0; 4];
-        cvt[0] = 255 * head[0] as usize + head[1] as usize;
-        cvt[1] = 255 * head[2] as usize + head[3] as usize;
-        cvt[2] = 255 * head[4] as usize + head[5] as usize;
-        return cvt;
-    }
-
-    fn build_header(&mut self, line: String) -> Vec<Vec<u8>> {
-        let mut ret = Vec::new();
-        let mut buffer: [u8; 8] = [0; 8];
-        let split = (line.len() + (MTU - 1)) / MTU;
-        buffer[0] = (self.rqid / 255) as u8;
-        buffer[1] = (self.rqid % 255) as u8;
-        buffer[4] = (split / 255) as u8;
-        buffer[5] = (split % 255) as u8;
-        for i in 0..split {
-            buffer[2] = (i / 255) as u8;
-            buffer[3] = (i % 255) as u8;
-            let last = if (i + 1) * MTU > line.len() { line.len() }
-                       else { (i + 1) * MTU };
-            ret.push([&buffer, line[i*MTU..last].as_bytes()].concat());
+    fn exchange(&mut self, framing: Framing, line: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let rqid = self.rqid;
+        self.rqid = self.rqid.wrapping_add(1);
+        let conn = self.conn.as_ref().unwrap();
+        let datagrams = match framing {
+            Framing::Header => frames(rqid, line),
+            Framing::Raw => line.chunks(MTU).map(<[u8]>::to_vec).collect(),
+        };
+        for datagram in datagrams {
+            conn.send(&datagram)?;
+        }
+        let expect = Expect::of(line);
+        if expect == Expect::Nothing {
+            return Ok(None);
         }
-        return ret;
+        return self.receive(framing, rqid, expect).map(Some);
     }
 
-    fn split_message(&mut self, line: String) -> Vec<Vec<u8>> {
-        let mut ret = Vec::new();
-        let split = (line.len() + (MTU - 1)) / MTU;
-        for i in 0..split {
-            let last = if (i + 1) * MTU > line.len() { line.len() }
-                       else { (i + 1) * MTU };
-            ret.push(line[i*MTU..last].as_bytes().to_vec());
+    /* Waits up to the timeout for every datagram of the reply to `rqid`. */
+    fn receive(&self, framing: Framing, rqid: u16, expect: Expect) -> io::Result<Vec<u8>> {
+        let conn = self.conn.as_ref().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(self.time.max(1));
+        let mut framed = Reassembly::new(rqid);
+        let mut raw = Vec::new();
+        let mut buf = [0; 65536];
+        loop {
+            match framing {
+                Framing::Header if framed.is_complete() => return Ok(framed.into_reply()),
+                Framing::Raw if !raw.is_empty() && reply::read(&mut raw.as_slice(), expect).is_ok() => return Ok(raw),
+                _ => (),
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                let missing = framed.missing();
+                return Err(io::Error::new(ErrorKind::TimedOut, match framing {
+                    Framing::Header if !missing.is_empty() =>
+                        format!("incomplete UDP reply: missing datagram(s) {:?}", missing),
+                    Framing::Raw if !raw.is_empty() => "incomplete UDP reply".to_string(),
+                    _ => "no UDP reply".to_string(),
+                }));
+            }
+            conn.set_read_timeout(Some(left))?;
+            match conn.recv(&mut buf) {
+                Ok(n) if framing == Framing::Header => framed.add(&buf[..n])?,
+                Ok(n) => raw.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => (),
+                Err(err) => return Err(err),
+            }
         }
-        return ret;
+    }
+
+    pub fn read(&mut self, expect: Expect) -> io::Result<Vec<u8>> {
+        let resp = self.resp.take().unwrap_or(Ok(Vec::new()));
+        if expect == Expect::Nothing { return Ok(Vec::new()); }
+        return resp;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagram(rqid: u16, seq: u16, total: u16, data: &[u8]) -> Vec<u8> {
+        return [&Header { rqid, seq, total, reserved: 0 }.encode()[..], data].concat();
+    }
+
+    #[test]
+    fn encodes_headers_big_endian() {
+        let header = Header { rqid: 300, seq: 2, total: 258, reserved: 0 };
+        assert_eq!(header.encode(), [0x01, 0x2c, 0x00, 0x02, 0x01, 0x02, 0x00, 0x00]);
+        assert_eq!(Header::decode(&header.encode()), Some(header));
+        assert_eq!(Header::decode(&[0xab, 0xcd, 0, 0, 0, 1, 0, 0, b'x']).unwrap().rqid, 0xabcd);
+        assert_eq!(Header::decode(&[0; HEADER - 1]), None);
+    }
+
+    #[test]
+    fn splits_requests_into_frames() {
+        let line = vec![b'x'; 2 * MTU + 10];
+        let sent = frames(1000, &line);
+        assert_eq!(sent.len(), 3);
+        for (seq, frame) in sent.iter().enumerate() {
+            assert_eq!(Header::decode(frame), Some(Header { rqid: 1000, seq: seq as u16, total: 3, reserved: 0 }));
+        }
+        assert_eq!(sent.iter().map(|f| f.len() - HEADER).collect::<Vec<_>>(), [MTU, MTU, 10]);
+        assert_eq!(frames(7, b""), [datagram(7, 0, 1, b"")]);
+    }
+
+    #[test]
+    fn reassembles_out_of_order() {
+        let mut reply = Reassembly::new(513);
+        reply.add(&datagram(513, 2, 3, b"END\r\n")).unwrap();
+        reply.add(&datagram(513, 0, 3, b"VALUE k 0 2\r\n")).unwrap();
+        assert!(!reply.is_complete());
+        reply.add(&datagram(513, 1, 3, b"hi\r\n")).unwrap();
+        assert!(reply.is_complete());
+        assert_eq!(reply.into_reply(), b"VALUE k 0 2\r\nhi\r\nEND\r\n");
+    }
+
+    #[test]
+    fn ignores_duplicates_and_other_requests() {
+        let mut reply = Reassembly::new(256);
+        reply.add(&datagram(256, 0, 2, b"first ")).unwrap();
+        reply.add(&datagram(256, 0, 2, b"again ")).unwrap();
+        reply.add(&datagram(0, 1, 2, b"other")).unwrap();
+        assert!(!reply.is_complete());
+        reply.add(&datagram(256, 1, 2, b"last")).unwrap();
+        assert_eq!(reply.into_reply(), b"first last");
+    }
+
+    #[test]
+    fn reports_missing_datagrams() {
+        let mut reply = Reassembly::new(1);
+        assert!(!reply.is_complete());
+        reply.add(&datagram(1, 0, 4, b"a")).unwrap();
+        reply.add(&datagram(1, 3, 4, b"d")).unwrap();
+        assert!(!reply.is_complete());
+        assert_eq!(reply.missing(), [1, 2]);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut reply = Reassembly::new(1);
+        assert!(reply.add(b"short").is_err());
+        assert!(reply.add(&datagram(1, 0, 0, b"")).is_err());
+        assert!(reply.add(&datagram(1, 2, 2, b"")).is_err());
+        reply.add(&datagram(1, 0, 2, b"a")).unwrap();
+        assert!(reply.add(&datagram(1, 1, 3, b"b")).is_err());
     }
 }
@@ -0,0 +1,158 @@
+use std::io::{self, Read, Write, ErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use super::error::ConnectError;
+
+const SESSION_TIMEOUT: i32 = 10000;
+const OP_GET_CHILDREN: i32 = 8;
+const OP_CLOSE_SESSION: i32 = -11;
+const ERR_NO_NODE: i32 = -101;
+/* The servers' default jute.maxbuffer; no reply can be longer. */
+const MAX_PACKET: usize = 0xfffff;
+
+/// Just enough of the ZooKeeper client protocol to read the ARCUS cache list.
+pub struct ZooKeeper {
+    conn: TcpStream,
+    xid: i32,
+}
+
+impl ZooKeeper {
+    /// Opens a session with the first reachable server in `ensemble` (`host:port,host:port,...`).
+    /// If none is, the error is the last server's; one that did not resolve
+    /// carries a `ConnectError::Resolve`.
+    pub fn connect(ensemble: &str) -> io::Result<ZooKeeper> {
+        let mut last_err = io::Error::new(ErrorKind::InvalidInput, "empty ZooKeeper ensemble");
+        for host in ensemble.split(',') {
+            let addrs = match host.to_socket_addrs() {
+                Ok(addrs) => addrs,
+                Err(err) => {
+                    last_err = io::Error::new(err.kind(), ConnectError::Resolve(host.to_string(), err));
+                    continue;
+                },
+            };
+            for addr in addrs {
+                let timeout = Duration::from_millis(SESSION_TIMEOUT as u64);
+                let session = TcpStream::connect_timeout(&addr, timeout)
+                    .and_then(|conn| {
+                        /* A stalled server counts as a dead one. */
+                        conn.set_read_timeout(Some(timeout))?;
+                        conn.set_write_timeout(Some(timeout))?;
+                        let mut zk = ZooKeeper { conn, xid: 0 };
+                        zk.handshake()?;
+                        Ok(zk)
+                    });
+                match session {
+                    Ok(zk) => return Ok(zk),
+                    Err(err) => last_err = io::Error::new(err.kind(), format!("{}: {}", addr, err)),
+                }
+            }
+        }
+        return Err(last_err);
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        let mut req = Vec::new();
+        req.extend_from_slice(&0i32.to_be_bytes());               /* protocolVersion */
+        req.extend_from_slice(&0i64.to_be_bytes());               /* lastZxidSeen */
+        req.extend_from_slice(&SESSION_TIMEOUT.to_be_bytes());    /* timeOut */
+        req.extend_from_slice(&0i64.to_be_bytes());               /* sessionId */
+        req.extend_from_slice(&16i32.to_be_bytes());              /* passwd */
+        req.extend_from_slice(&[0; 16]);
+        self.send(&req)?;
+        let resp = self.recv()?;
+        if resp.len() < 16 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "short ZooKeeper handshake"));
+        }
+        return Ok(());
+    }
+
+    /// Lists the children of `path`.
+    pub fn children(&mut self, path: &str) -> io::Result<Vec<String>> {
+        self.xid += 1;
+        let mut req = Vec::new();
+        req.extend_from_slice(&self.xid.to_be_bytes());
+        req.extend_from_slice(&OP_GET_CHILDREN.to_be_bytes());
+        req.extend_from_slice(&(path.len() as i32).to_be_bytes());
+        req.extend_from_slice(path.as_bytes());
+        req.push(0);                                               /* watch */
+        self.send(&req)?;
+
+        let resp = self.recv()?;
+        let mut rbuf = resp.as_slice();
+        let _xid = read_i32(&mut rbuf)?;
+        let _zxid = read_i64(&mut rbuf)?;
+        match read_i32(&mut rbuf)? {
+            0 => (),
+            ERR_NO_NODE => return Err(io::Error::new(ErrorKind::NotFound, format!("no such znode: {}", path))),
+            err => return Err(io::Error::other(format!("ZooKeeper error {} on {}", err, path))),
+        }
+        let count = read_i32(&mut rbuf)?.max(0);
+        let mut children = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let len = read_i32(&mut rbuf)?.max(0) as usize;
+            if rbuf.len() < len {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "truncated ZooKeeper reply"));
+            }
+            children.push(String::from_utf8_lossy(&rbuf[..len]).into_owned());
+            rbuf = &rbuf[len..];
+        }
+        return Ok(children);
+    }
+
+    fn send(&mut self, req: &[u8]) -> io::Result<()> {
+        self.conn.write_all(&(req.len() as i32).to_be_bytes())?;
+        self.conn.write_all(req)
+    }
+
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.conn.read_exact(&mut len).map_err(timed_out)?;
+        let len = i32::from_be_bytes(len).max(0) as usize;
+        if len > MAX_PACKET {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("ZooKeeper reply of {} bytes is over the {} byte limit", len, MAX_PACKET)));
+        }
+        let mut resp = vec![0; len];
+        self.conn.read_exact(&mut resp).map_err(timed_out)?;
+        return Ok(resp);
+    }
+}
+
+impl Drop for ZooKeeper {
+    fn drop(&mut self) {
+        let mut req = Vec::new();
+        req.extend_from_slice(&(self.xid + 1).to_be_bytes());
+        req.extend_from_slice(&OP_CLOSE_SESSION.to_be_bytes());
+        let _ = self.send(&req);
+    }
+}
+
+/* Read timeouts come back as WouldBlock on Unix. */
+fn timed_out(err: io::Error) -> io::Error {
+    match err.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut =>
+            io::Error::new(ErrorKind::TimedOut, format!("no ZooKeeper reply within {}ms", SESSION_TIMEOUT)),
+        _ => err,
+    }
+}
+
+fn read_i32(rbuf: &mut &[u8]) -> io::Result<i32> {
+    let mut buf = [0; 4];
+    rbuf.read_exact(&mut buf)?;
+    return Ok(i32::from_be_bytes(buf));
+}
+
+fn read_i64(rbuf: &mut &[u8]) -> io::Result<i64> {
+    let mut buf = [0; 8];
+    rbuf.read_exact(&mut buf)?;
+    return Ok(i64::from_be_bytes(buf));
+}
+
+/// Reads the cache nodes of an ARCUS service code, as `host:port` addresses.
+pub fn cache_list(ensemble: &str, service_code: &str) -> io::Result<Vec<String>> {
+    let mut zk = ZooKeeper::connect(ensemble)?;
+    let nodes = zk.children(&format!("/arcus/cache_list/{}", service_code))?;
+    /* Znodes are named "<ip>:<port>-<hostname>". */
+    return Ok(nodes.iter()
+                   .map(|n| n.split_once('-').map_or(n.as_str(), |(addr, _)| addr).to_string())
+                   .collect());
+}
@@ -0,0 +1,192 @@
+use super::tree::Tree;
+
+/* Arguments the CLI computes from the data block, and how. */
+const LENGTHS: [(&str, Option<&str>); 3] = [
+    ("bytes", None),
+    ("lenkeys", Some("numkeys")),
+    ("lenfields", Some("numfields")),
+];
+
+/// Expands `<command> -- <data>` into the command line followed by its data block,
+/// with `<bytes>` (or `<lenkeys> <numkeys>`, `<lenfields> <numfields>`) filled in.
+/// Returns `Ok(None)` when the line has no `--` separator.
+pub fn expand(tree: &Tree, line: &str) -> Result<Option<Vec<u8>>, String> {
+    let (head, data) = match line.split_once(" -- ") {
+        Some(split) => split,
+        None => match line.strip_suffix(" --") {
+            Some(head) => (head, ""),
+            None => return Ok(None),
+        },
+    };
+    let words: Vec<&str> = head.split_whitespace().collect();
+
+    for (len, num) in LENGTHS {
+        let (data, lengths) = match num {
+            None => {
+                let data = unescape(data)?;
+                let lengths = vec![data.len().to_string()];
+                (data, lengths)
+            },
+            Some(_) => {
+                let list = data.split_whitespace().collect::<Vec<_>>();
+                let data = list.join(" ").into_bytes();
+                (data.clone(), vec![data.len().to_string(), list.len().to_string()])
+            },
+        };
+        for at in 1..=words.len() {
+            let mut line = words[..at].to_vec();
+            line.extend(lengths.iter().map(String::as_str));
+            line.extend_from_slice(&words[at..]);
+            let line = line.join(" ");
+            let matched = match tree.parse(&line) {
+                Ok(Some(matched)) => matched,
+                _ => continue,
+            };
+            let placed = |name: &str, index: usize| {
+                matched.args.iter().any(|a| a.name == name && a.index == index)
+            };
+            if !placed(len, at) || !num.is_none_or(|n| placed(n, at + 1)) {
+                continue;
+            }
+
+            let mut req = line.into_bytes();
+            req.extend_from_slice(b"\r\n");
+            if matched.nread() {
+                req.extend_from_slice(&data);
+                req.extend_from_slice(b"\r\n");
+            }
+            return Ok(Some(req));
+        }
+    }
+    return Err(format!("no place for the data length in \"{}\"", head));
+}
+
+/* Data after "--" may spell out binary bytes as \r, \n, \t, \0, \\ and \xHH. */
+fn unescape(data: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'r') => out.push(b'\r'),
+            Some(b'n') => out.push(b'\n'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'0') => out.push(b'\0'),
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'x') => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                let byte = std::str::from_utf8(&hex).ok()
+                                                    .filter(|h| h.len() == 2)
+                                                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                match byte {
+                    Some(byte) => out.push(byte),
+                    None => return Err("invalid \\x escape in data".to_string()),
+                }
+            },
+            Some(c) => return Err(format!("unknown escape \\{} in data", c as char)),
+            None => return Err("trailing \\ in data".to_string()),
+        }
+    }
+    return Ok(out);
+}
+
+/// Whether `input` still lacks (part of) the data block its command line asks for.
+/// Data lines are separated by `\n` and go out as `\r\n`.
+pub fn incomplete(tree: &Tree, input: &str) -> Result<bool, String> {
+    let (head, data) = match input.split_once('\n') {
+        Some((head, data)) => (head, Some(data)),
+        None => (input, None),
+    };
+    if data.is_none() && (head.contains(" -- ") || head.ends_with(" --")) {
+        return expand(tree, head).map(|_| false);
+    }
+    let matched = match tree.parse(head)? {
+        Some(matched) if matched.nread() => matched,
+        _ => return Ok(false),
+    };
+    let data = match data {
+        Some(data) => data,
+        None => return Ok(true),
+    };
+    return match matched.arg("bytes").and_then(|b| b.parse::<usize>().ok()) {
+        Some(bytes) => {
+            let len = data.len() + data.matches('\n').count();
+            if len > bytes {
+                Err(format!("data is {} bytes long, expected {}", len, bytes))
+            } else {
+                Ok(len < bytes)
+            }
+        },
+        None => Ok(data.trim().is_empty()),
+    };
+}
+
+/// Joins a command line and its data lines into a single request.
+pub fn join(input: &str) -> Vec<u8> {
+    let mut req = input.replace('\n', "\r\n").into_bytes();
+    req.extend_from_slice(b"\r\n");
+    return req;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::arcus_hints;
+
+    fn tree() -> Tree {
+        let hints = arcus_hints();
+        return Tree::new(hints.iter().map(|h| h.display.as_str()));
+    }
+
+    fn expanded(line: &str) -> String {
+        return String::from_utf8(expand(&tree(), line).unwrap().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn places_bytes() {
+        assert_eq!(expanded("set k 0 0 -- hello"), "set k 0 0 5\r\nhello\r\n");
+        assert_eq!(expanded("set k 0 0 -- hello noreply"), "set k 0 0 13\r\nhello noreply\r\n");
+        assert_eq!(expanded("lop insert k -1 -- v"), "lop insert k -1 1\r\nv\r\n");
+        assert_eq!(expanded("bop insert k 10 0x01 -- abc"), "bop insert k 10 0x01 3\r\nabc\r\n");
+        assert_eq!(expanded("mop insert k f1 -- ab"), "mop insert k f1 2\r\nab\r\n");
+        assert_eq!(expanded("set k 0 0 --"), "set k 0 0 0\r\n\r\n");
+    }
+
+    #[test]
+    fn places_lengths_and_counts() {
+        assert_eq!(expanded("mget -- a bb c"), "mget 6 3\r\na bb c\r\n");
+        assert_eq!(expanded("mop delete k -- f1  f2"), "mop delete k 5 2\r\nf1 f2\r\n");
+        assert_eq!(expanded("bop mget 0..10 5 -- k1 k2"), "bop mget 5 2 0..10 5\r\nk1 k2\r\n");
+    }
+
+    #[test]
+    fn unescapes_data() {
+        assert_eq!(expanded("set k 0 0 -- a\\r\\nb"), "set k 0 0 4\r\na\r\nb\r\n");
+        assert_eq!(expanded("set k 0 0 -- \\x41\\\\"), "set k 0 0 2\r\nA\\\r\n");
+        assert!(expand(&tree(), "set k 0 0 -- \\x4").is_err());
+        assert!(expand(&tree(), "set k 0 0 -- \\q").is_err());
+    }
+
+    #[test]
+    fn leaves_other_lines() {
+        assert!(expand(&tree(), "set k 0 0 5").unwrap().is_none());
+        assert!(expand(&tree(), "get k -- x").is_err());
+    }
+
+    #[test]
+    fn waits_for_data() {
+        let tree = tree();
+        assert!(incomplete(&tree, "set k 0 0 5").unwrap());
+        assert!(incomplete(&tree, "set k 0 0 5\nab").unwrap());
+        assert!(!incomplete(&tree, "set k 0 0 5\nab\nc").unwrap());
+        assert!(incomplete(&tree, "set k 0 0 5\nabcdef").is_err());
+        assert!(!incomplete(&tree, "set k 0 0 -- abc").unwrap());
+        assert!(!incomplete(&tree, "get k").unwrap());
+        assert!(incomplete(&tree, "mget 3 2").unwrap());
+        assert!(!incomplete(&tree, "mget 3 2\na b").unwrap());
+        assert_eq!(join("set k 0 0 4\nab\nc"), b"set k 0 0 4\r\nab\r\nc\r\n");
+    }
+}
@@ -1,19 +1,125 @@
+pub mod block;
+pub mod tree;
+
 use std::borrow::Cow::{self, Owned};
 use std::collections::HashSet;
 use rustyline::Context;
+use rustyline::completion::{Completer, Pair};
 use rustyline::highlight::Highlighter;
 use rustyline::hint::{Hint,Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline_derive::{Completer, Helper, Highlighter, Validator, Hinter};
+use self::tree::{Matched, Tree};
 
-#[derive(Helper, Completer, Hinter, Validator)]
+#[derive(Helper, Hinter)]
 pub struct MyHelper {
     #[rustyline(Hinter)]
     hinter: CMDHinter,
+    tree: Tree,
 }
 
 impl MyHelper {
     pub fn new() -> Self {
-        MyHelper { hinter: CMDHinter { hints: arcus_hints() } }
+        let hints = arcus_hints();
+        let tree = Tree::new(hints.iter().map(|h| h.display.as_str()));
+        MyHelper { hinter: CMDHinter { hints }, tree }
+    }
+
+    /// Checks a command line against the ARCUS grammar before it is sent.
+    pub fn check(&self, line: &str) -> Result<Option<Matched>, String> {
+        self.tree.parse(line)
+    }
+
+    /// Builds the full request for `<command> -- <data>` input.
+    pub fn expand(&self, line: &str) -> Result<Option<Vec<u8>>, String> {
+        block::expand(&self.tree, line)
+    }
+
+    /// Whether `input` still waits for (part of) its data block, or for the
+    /// closing `}` of a `pipeline { ... }` block.
+    pub fn incomplete(&self, input: &str) -> Result<bool, String> {
+        if is_pipeline(input) {
+            if input.lines().skip(1).any(|l| l.trim() == "}") {
+                return self.pipeline(input).map(|_| false);
+            }
+            return Ok(true);
+        }
+        block::incomplete(&self.tree, input)
+    }
+
+    /// The requests of a `pipeline { ... }` block, one per command and its data.
+    pub fn pipeline(&self, input: &str) -> Result<Vec<Vec<u8>>, String> {
+        let mut lines = input.lines().skip(1).map(|l| l.trim_end_matches('\r'));
+        let mut reqs = Vec::new();
+        while let Some(line) = lines.next() {
+            /* Data lines lose the indentation of their command line, and nothing more. */
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let line = line.trim_start();
+            if line == "}" { break }
+            if line.is_empty() || line.starts_with('#') { continue }
+            let mut command = line.to_string();
+            while block::incomplete(&self.tree, &command).map_err(|err| format!("{}: {}", line, err))? {
+                match lines.next() {
+                    Some(data) => {
+                        command.push('\n');
+                        command.push_str(data.strip_prefix(indent).unwrap_or(data));
+                    },
+                    None => break,
+                }
+            }
+            reqs.push(self.request(&command).map_err(|err| format!("{}: {}", line, err))?);
+        }
+        return Ok(reqs);
+    }
+
+    /// Turns complete input, data lines included, into the request to send.
+    pub fn request(&self, input: &str) -> Result<Vec<u8>, String> {
+        if input.contains('\n') { return Ok(block::join(input)); }
+        if let Some(req) = self.expand(input)? { return Ok(req); }
+        self.check(input)?;
+        Ok(input.as_bytes().to_vec())
+    }
+}
+
+impl Default for MyHelper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `input` opens a `pipeline { ... }` block.
+pub fn is_pipeline(input: &str) -> bool {
+    input.lines().next().is_some_and(|l| l.split_whitespace().eq(["pipeline", "{"]))
+}
+
+impl Completer for MyHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        /* Spaces may be wider than a byte, like the ideographic space of Korean IMEs. */
+        let start = line.char_indices()
+                        .rfind(|(_, c)| c.is_whitespace())
+                        .map_or(0, |(i, c)| i + c.len_utf8());
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        let prefix = &line[start..];
+        let mut words = self.tree.complete(&words);
+        words.retain(|w| w.starts_with(prefix));
+        words.sort();
+        Ok((start, words.into_iter().map(|w| Pair {
+            replacement: w.clone() + " ",
+            display: w,
+        }).collect()))
+    }
+}
+
+impl Validator for MyHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(match self.incomplete(ctx.input()) {
+            Ok(true) => ValidationResult::Incomplete,
+            Ok(false) => ValidationResult::Valid(None),
+            Err(err) => ValidationResult::Invalid(Some(format!(" --< {}", err))),
+        })
     }
 }
 
@@ -78,18 +184,21 @@ impl CMDHint {
 pub fn arcus_hints() -> HashSet<CMDHint> {
     let mut set = HashSet::new();
     set.insert(CMDHint::new("quit", "quit"));
+    set.insert(CMDHint::new("pipeline {", "pipeline {"));
+    set.insert(CMDHint::new("\\format [raw|pretty|json]", "\\format "));
+    set.insert(CMDHint::new("\\stats [settings|items|slabs|prefix|prefixes|zookeeper] [--watch <seconds> [--count <samples>]]", "\\stats "));
     // K/V
-    set.insert(CMDHint::new("get <key>", "get "));
-    set.insert(CMDHint::new("gets <key>", "gets "));
+    set.insert(CMDHint::new("get <key> [<key> ...]", "get "));
+    set.insert(CMDHint::new("gets <key> [<key> ...]", "gets "));
     set.insert(CMDHint::new("mget <lenkeys> <numkeys>\n<\"space separated keys\">", "mget "));
     set.insert(CMDHint::new("mgets <lenkeys> <numkeys>\n<\"space separated keys\">", "mgets "));
     set.insert(CMDHint::new("set <key> <flags> <exptime> <bytes> [noreply]\n<data>", "set "));
-    set.insert(CMDHint::new("cas <key> <flags> <exptime> <bytes> [noreply]\n<data>", "cas "));
+    set.insert(CMDHint::new("cas <key> <flags> <exptime> <bytes> <cas_unique> [noreply]\n<data>", "cas "));
     set.insert(CMDHint::new("add <key> <flags> <exptime> <bytes> [noreply]\n<data>", "add "));
     set.insert(CMDHint::new("append <key> <flags> <exptime> <bytes> [noreply]\n<data>", "append "));
     set.insert(CMDHint::new("prepend <key> <flags> <exptime> <bytes> [noreply]\n<data>", "prepend "));
     set.insert(CMDHint::new("replace <key> <flags> <exptime> <bytes> [noreply]\n<data>", "replace "));
-    set.insert(CMDHint::new("delete <key> [noreply]", "delete "));
+    set.insert(CMDHint::new("delete <key> [<time>] [noreply]", "delete "));
     set.insert(CMDHint::new("incr <key> <delta> [<flags> <exptime> <initial>] [noreply]", "incr "));
     set.insert(CMDHint::new("decr <key> <delta> [<flags> <exptime> <initial>] [noreply]", "decr "));
     // List
@@ -134,14 +243,15 @@ pub fn arcus_hints() -> HashSet<CMDHint> {
     set.insert(CMDHint::new("flush_all [<delay>] [noreply]", "flush_all "));
     set.insert(CMDHint::new("flush_prefix <prefix> [<delay>] [noreply]", "flush_prefix "));
     set.insert(CMDHint::new("scrub [stale]", "scrub "));
-    set.insert(CMDHint::new("stats [settings|items|slabs|prefix|zookeeper]", "stats "));
+    set.insert(CMDHint::new("stats [settings|items|slabs|prefix|prefixes|zookeeper]", "stats "));
+    set.insert(CMDHint::new("stats detail on|off|dump", "stats detail "));
     set.insert(CMDHint::new("stats cachedump <slab_clsid> <limit> [forward|backward [sticky]]", "stats cachedump "));
     set.insert(CMDHint::new("stats dump", "stats dump"));
     set.insert(CMDHint::new("config verbosity [<verbose>]", "config verbosity "));
     set.insert(CMDHint::new("config memlimit [<memsize>]", "config memlimit "));
     set.insert(CMDHint::new("config zkfailstop [on|off]", "config zkfailstop "));
     set.insert(CMDHint::new("config hbtimeout [<hbtimeout>]", "config hbtimeout "));
-    set.insert(CMDHint::new("config hbfailstop [hbfailstop]", "config hbfailstop "));
+    set.insert(CMDHint::new("config hbfailstop [on|off]", "config hbfailstop "));
     set.insert(CMDHint::new("config maxconns [<maxconn>]", "config maxconns "));
     set.insert(CMDHint::new("config max_list_size [<max_size>]", "config max_list_size "));
     set.insert(CMDHint::new("config max_set_size [<max_size>]", "config max_set_size "));
@@ -159,3 +269,21 @@ pub fn arcus_hints() -> HashSet<CMDHint> {
     // Etc
     set
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    fn complete(line: &str) -> (usize, Vec<String>) {
+        let history = DefaultHistory::new();
+        let (start, pairs) = MyHelper::new().complete(line, line.len(), &Context::new(&history)).unwrap();
+        return (start, pairs.into_iter().map(|p| p.display).collect());
+    }
+
+    #[test]
+    fn completes_after_wide_spaces() {
+        assert_eq!(complete("bop\u{3000}ins"), (6, vec!["insert".to_string()]));
+        assert_eq!(complete("bop ins"), (4, vec!["insert".to_string()]));
+    }
+}
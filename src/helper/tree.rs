@@ -1,34 +1,253 @@
 use std::rc::Rc;
-use std::collections::HashSet;
-use std::hash::{Hash, Hasher};
 use std::cell::RefCell;
+use std::collections::HashMap;
+
+/* Expansions for placeholders that the hints use without spelling them out. */
+const DEFINITIONS: [(&str, &str); 6] = [
+    ("attributes", "<flags> <exptime> <maxcount> [<ovflaction>] [unreadable]"),
+    ("eflag_filter", "<fwhere> [<bitwop> <foperand>] <compop> <fvalue>"),
+    ("eflag_update", "[<fwhere> <bitwop>] <fvalue>"),
+    ("compop", "EQ | NE | LT | LE | GT | GE"),
+    ("ovflaction", "error | head_trim | tail_trim | smallest_trim | largest_trim | smallest_silent_trim | largest_silent_trim"),
+    ("order", "asc | desc"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum Kind {
+    #[default]
+    Word,
+    Any,
+    Number,
+    Unsigned,
+    Hex,
+    Bkey,
+    Range,
+    BitOp,
+    Pair,
+}
+
+impl Kind {
+    fn of(name: &str) -> Kind {
+        if name.contains('=') { return Kind::Pair; }
+        if name.contains("range") { return Kind::Range; }
+        match name {
+            "bkey" => Kind::Bkey,
+            "eflag" | "fvalue" | "foperand" => Kind::Hex,
+            "bitwop" => Kind::BitOp,
+            "exptime" | "index" | "bytes" | "maxcount" => Kind::Number,
+            "flags" | "cas_unique" | "delta" | "initial" | "lenkeys" | "numkeys" |
+            "lenfields" | "numfields" | "count" | "offset" | "fwhere" | "position" |
+            "delay" | "slab_clsid" | "limit" | "cursor" | "verbose" | "memsize" |
+            "hbtimeout" | "maxconn" | "max_size" | "maxbytes" | "scrub_count" | "time" => Kind::Unsigned,
+            _ => Kind::Any,
+        }
+    }
+
+    fn accepts(&self, word: &str) -> bool {
+        match self {
+            Kind::Word => false,
+            Kind::Any => true,
+            Kind::Number => unsigned(word.strip_prefix('-').unwrap_or(word)),
+            Kind::Unsigned => unsigned(word),
+            Kind::Hex => word.split(',').all(hex),
+            Kind::Bkey => unsigned(word) || hex(word),
+            Kind::Range => word.split("..").all(|w| {
+                unsigned(w.strip_prefix('-').unwrap_or(w)) || hex(w)
+            }) && word.split("..").count() <= 2,
+            Kind::BitOp => matches!(word, "&" | "|" | "^"),
+            Kind::Pair => word.contains('=') && !word.starts_with('='),
+        }
+    }
+}
+
+fn unsigned(word: &str) -> bool {
+    !word.is_empty() && word.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn hex(word: &str) -> bool {
+    match word.strip_prefix("0x").or(word.strip_prefix("0X")) {
+        Some(digits) => !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_hexdigit()),
+        None => false,
+    }
+}
 
 #[derive(Default)]
-struct Node {
+pub struct Node {
     pub token: String,
     pub skip: bool,
     nread: bool,
     anything: bool,
-    children: HashSet<Rc<RefCell<Node>>>,
+    more: bool,
+    last: bool,
+    kind: Kind,
+    children: Vec<Rc<RefCell<Node>>>,
 }
 
-impl PartialEq for Node {
-    fn eq(&self, other: &Self) -> bool {
-        self.token == other.token
+impl Node {
+    fn new(token: &str, skip: bool) -> Node {
+        let anything = token.starts_with('<');
+        Node {
+            token: token.to_string(),
+            skip,
+            anything,
+            kind: if anything { Kind::of(name_of(token)) } else { Kind::Word },
+            ..Default::default()
+        }
+    }
+
+    fn add_child(&mut self, token: &str, skip: bool, more: bool) -> Rc<RefCell<Node>> {
+        let found = self.children.iter().find(|c| {
+            let c = c.borrow();
+            c.token == token && c.skip == skip && c.more == more
+        });
+        if let Some(child) = found { return child.clone(); }
+        let mut node = Node::new(token, skip);
+        node.more = more;
+        let child = Rc::new(RefCell::new(node));
+        self.children.push(child.clone());
+        return child;
+    }
+
+    fn accepts(&self, word: &str) -> bool {
+        if self.anything { self.kind.accepts(word) } else { self.token == word }
+    }
+
+    fn suggest(&self, out: &mut Vec<String>) {
+        for child in self.children.iter() {
+            let child = child.borrow();
+            if !child.anything && !out.contains(&child.token) {
+                out.push(child.token.clone());
+            }
+            if child.skip { child.suggest(out); }
+        }
+    }
+
+    /* A command may end here, directly or by passing over optional children. */
+    fn finish(&self) -> Option<bool> {
+        if self.last { return Some(self.nread); }
+        self.children.iter()
+                     .filter(|c| c.borrow().skip)
+                     .find_map(|c| c.borrow().finish())
     }
 }
 
-impl Eq for Node {}
+enum Elem {
+    Token(String, bool),
+    Group(Vec<Vec<Elem>>, bool),
+}
+
+fn tokenize(spec: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = spec.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() { chars.next(); continue; }
+        if c == '[' || c == ']' || c == '|' {
+            tokens.push(c.to_string());
+            chars.next();
+            continue;
+        }
+        /* "noreply|pipe" binds tighter than a spaced " | " between sequences. */
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '[' || c == ']' { break; }
+            if c == '<' {
+                for c in chars.by_ref() {
+                    word.push(c);
+                    if c == '>' { break; }
+                }
+                continue;
+            }
+            word.push(c);
+            chars.next();
+        }
+        tokens.push(word);
+    }
+    return tokens;
+}
 
-impl Hash for Node {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.token.hash(state);
+fn parse_alts(tokens: &[String], pos: &mut usize) -> Vec<Vec<Elem>> {
+    let mut alts = vec![Vec::new()];
+    while *pos < tokens.len() {
+        let token = &tokens[*pos];
+        *pos += 1;
+        match token.as_str() {
+            "]" => break,
+            "|" => alts.push(Vec::new()),
+            "[" => {
+                let group = parse_alts(tokens, pos);
+                alts.last_mut().unwrap().push(Elem::Group(group, true));
+            },
+            "..." => {
+                if let Some(Elem::Token(_, more)) = alts.last_mut().unwrap().last_mut() {
+                    *more = true;
+                }
+            },
+            _ => alts.last_mut().unwrap().push(Elem::Token(token.clone(), false)),
+        }
     }
+    return alts;
 }
 
-impl Node {
-    fn add_child(&mut self, child: Rc<Node>) {
-        self.children.insert(child);
+fn parse(spec: &str) -> Vec<Elem> {
+    let tokens = tokenize(spec);
+    let mut alts = parse_alts(&tokens, &mut 0);
+    if alts.len() == 1 { alts.pop().unwrap() } else { vec![Elem::Group(alts, false)] }
+}
+
+fn choices(token: &str) -> Vec<&str> {
+    if token.starts_with('<') { vec![token] } else { token.split('|').collect() }
+}
+
+fn name_of(token: &str) -> &str {
+    token.trim().trim_start_matches('<').trim_end_matches('>')
+}
+
+/// A placeholder and the input word it matched.
+pub struct Arg {
+    pub name: String,
+    pub index: usize,
+}
+
+/// Outcome of matching one command line against the grammar.
+pub struct Matched {
+    pub words: Vec<String>,
+    pub args: Vec<Arg>,
+    nread: bool,
+}
+
+impl Matched {
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args.iter()
+                 .find(|a| a.name == name)
+                 .map(|a| self.words[a.index].as_str())
+    }
+
+    /// Whether a data block has to follow the command line.
+    pub fn nread(&self) -> bool {
+        if !self.nread { return false; }
+        match (self.arg("numfields"), self.arg("bytes")) {
+            (Some(n), _) => n != "0",
+            (_, Some(b)) => !b.starts_with('-'),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Failure {
+    at: usize,
+    expected: Vec<String>,
+}
+
+impl Failure {
+    fn expect(&mut self, at: usize, token: &str) {
+        if at > self.at {
+            self.at = at;
+            self.expected.clear();
+        }
+        if at == self.at && !self.expected.iter().any(|e| e == token) {
+            self.expected.push(token.to_string());
+        }
     }
 }
 
@@ -37,31 +256,238 @@ pub struct Tree {
 }
 
 impl Tree {
-    fn insert(&mut self, input: String) {
+    pub fn new<'a>(hints: impl IntoIterator<Item = &'a str>) -> Tree {
+        let mut tree = Tree { root: Node::default() };
+        for hint in hints {
+            tree.insert(hint);
+        }
+        return tree;
+    }
+
+    fn insert(&mut self, input: &str) {
         let mut lines = input.lines();
-        let mut _vstr: Vec<_> = lines.next().unwrap_or("").split_whitespace().collect();
-        for line in lines {
-            _vstr.push(line);
+        let command = parse(lines.next().unwrap_or(""));
+        let mut defs: HashMap<String, Vec<Elem>> = DEFINITIONS.iter()
+            .map(|(name, body)| (name.to_string(), parse(body)))
+            .collect();
+        let mut nread = false;
+        for line in lines.map(str::trim).filter(|l| !l.is_empty()) {
+            match line.strip_prefix('*') {
+                Some(def) => {
+                    let (name, body) = def.split_once(':')
+                                          .or(def.split_once('='))
+                                          .unwrap_or((def, ""));
+                    defs.insert(name_of(name).to_string(), parse(body));
+                },
+                None => nread = true,
+            }
+        }
+
+        let root = Rc::new(RefCell::new(std::mem::take(&mut self.root)));
+        let tails = Self::extend(vec![root.clone()], &command, &defs);
+        for tail in tails {
+            let mut tail = tail.borrow_mut();
+            tail.last = true;
+            tail.nread = nread;
+        }
+        self.root = Rc::try_unwrap(root).ok().unwrap().into_inner();
+    }
+
+    fn extend(mut heads: Vec<Rc<RefCell<Node>>>, elems: &[Elem],
+              defs: &HashMap<String, Vec<Elem>>) -> Vec<Rc<RefCell<Node>>> {
+        for elem in elems {
+            heads = match elem {
+                Elem::Token(token, more) => match defs.get(name_of(token)) {
+                    Some(body) if token.starts_with('<') => Self::extend(heads, body, defs),
+                    _ => heads.iter().flat_map(|h| choices(token).into_iter().map(move |t| {
+                        h.borrow_mut().add_child(t, false, *more)
+                    })).collect(),
+                },
+                Elem::Group(alts, optional) => {
+                    let single = alts.iter().all(|alt| matches!(alt.as_slice(),
+                        [Elem::Token(t, _)] if !defs.contains_key(name_of(t))));
+                    if *optional && single {
+                        heads.iter().flat_map(|h| alts.iter().flat_map(move |alt| match &alt[0] {
+                            Elem::Token(token, more) => choices(token).into_iter().map(|t| {
+                                h.borrow_mut().add_child(t, true, *more)
+                            }).collect::<Vec<_>>(),
+                            Elem::Group(..) => unreachable!(),
+                        })).collect()
+                    } else {
+                        let mut next: Vec<_> = alts.iter()
+                            .flat_map(|alt| Self::extend(heads.clone(), alt, defs))
+                            .collect();
+                        if *optional { next.extend(heads); }
+                        next
+                    }
+                },
+            };
+        }
+        return heads;
+    }
+
+    /// Matches a command line against the grammar.
+    /// Lines whose command word is unknown to the grammar give `Ok(None)`.
+    pub fn parse(&self, line: &str) -> Result<Option<Matched>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || !self.root.children.iter().any(|c| c.borrow().accepts(words[0])) {
+            return Ok(None);
+        }
+
+        let mut args = Vec::new();
+        let mut fail = Failure::default();
+        return match Self::walk(&self.root, &words, 0, &mut args, &mut fail) {
+            Some(nread) => Ok(Some(Matched {
+                words: words.iter().map(|w| w.to_string()).collect(),
+                args,
+                nread,
+            })),
+            None if fail.at < words.len() =>
+                Err(format!("unexpected \"{}\", expected {}", words[fail.at], fail.expected.join(" | "))),
+            None => Err(format!("missing {}", fail.expected.join(" | "))),
+        };
+    }
+
+    /// Keywords that may follow the given words.
+    pub fn complete(&self, words: &[&str]) -> Vec<String> {
+        let mut out = Vec::new();
+        Self::follow(&self.root, words, 0, &mut out);
+        return out;
+    }
+
+    fn follow(node: &Node, words: &[&str], at: usize, out: &mut Vec<String>) {
+        if at == words.len() { return node.suggest(out); }
+        if node.more && node.accepts(words[at]) {
+            Self::follow(node, words, at + 1, out);
+        }
+        Self::follow_children(node, words, at, out);
+    }
+
+    fn follow_children(node: &Node, words: &[&str], at: usize, out: &mut Vec<String>) {
+        for child in node.children.iter() {
+            let child = child.borrow();
+            if child.accepts(words[at]) {
+                Self::follow(&child, words, at + 1, out);
+            }
+            if child.skip {
+                Self::follow_children(&child, words, at, out);
+            }
         }
     }
-    fn initialize(&mut self) {
-        Node { token: "get", false, false, false, HashSet<Rc<}
+
+    fn walk(node: &Node, words: &[&str], at: usize,
+            args: &mut Vec<Arg>, fail: &mut Failure) -> Option<bool> {
+        if let Some(nread) = node.finish() {
+            if at == words.len() { return Some(nread); }
+            fail.expect(at, "end of line");
+        }
+        if node.more && at < words.len() && node.accepts(words[at]) {
+            args.push(Arg { name: name_of(&node.token).to_string(), index: at });
+            if let Some(nread) = Self::walk(node, words, at + 1, args, fail) { return Some(nread); }
+            args.pop();
+        }
+        Self::step(node, words, at, args, fail)
+    }
+
+    fn step(node: &Node, words: &[&str], at: usize,
+            args: &mut Vec<Arg>, fail: &mut Failure) -> Option<bool> {
+        for child in node.children.iter() {
+            let child = child.borrow();
+            if at < words.len() && child.accepts(words[at]) {
+                if child.anything {
+                    args.push(Arg { name: name_of(&child.token).to_string(), index: at });
+                }
+                if let Some(nread) = Self::walk(&child, words, at + 1, args, fail) { return Some(nread); }
+                if child.anything { args.pop(); }
+            } else {
+                fail.expect(at, &child.token);
+            }
+            if child.skip {
+                if let Some(nread) = Self::step(&child, words, at, args, fail) { return Some(nread); }
+            }
+        }
+        None
     }
 }
 
-fn main() {
-    let input = "hello world from rust\nthis is the second line\nthird line here";
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::arcus_hints;
+
+    fn tree() -> Tree {
+        let hints = arcus_hints();
+        return Tree::new(hints.iter().map(|h| h.display.as_str()));
+    }
+
+    #[test]
+    fn accepts_commands() {
+        let tree = tree();
+        for line in ["get a b c", "set k 0 0 2", "set k 0 -1 2 noreply", "delete k", "delete k 0",
+                     "delete k 0 noreply", "delete k noreply", "lop get k 0..-1 delete",
+                     "bop insert k 0x01 0x0F 2 create 0 0 10 noreply", "bop get k 0..10 0 EQ 0x01 5",
+                     "bop smget 3 2 0..100 10 unique", "setattr k maxcount=10 expiretime=0",
+                     "stats", "stats prefixes", "stats detail on", "stats detail off", "stats detail dump",
+                     "config zkfailstop on", "cmdlog start /tmp/log"] {
+            assert!(tree.parse(line).is_ok_and(|m| m.is_some()), "{}", line);
+        }
+    }
 
-    let mut lines = input.lines();
+    #[test]
+    fn rejects_commands() {
+        let tree = tree();
+        assert_eq!(tree.parse("set k 0 0").err().unwrap(), "missing <bytes>");
+        assert_eq!(tree.parse("set k x 0 2").err().unwrap(), "unexpected \"x\", expected <flags>");
+        assert!(tree.parse("delete k 0 extra").is_err());
+        assert!(tree.parse("delete k -1").is_err());
+        assert!(tree.parse("stats detail maybe").is_err());
+        assert!(tree.parse("bop insert k 0xZZ 2").is_err());
+        assert!(tree.parse("lop get k 0..1..2").is_err());
+    }
+
+    #[test]
+    fn passes_unknown_commands() {
+        let tree = tree();
+        assert!(tree.parse("version").is_ok_and(|m| m.is_none()));
+        assert!(tree.parse("").is_ok_and(|m| m.is_none()));
+    }
+
+    #[test]
+    fn detects_data_blocks() {
+        let tree = tree();
+        let nread = |line: &str| tree.parse(line).unwrap().unwrap().nread();
+        assert!(nread("set k 0 0 2"));
+        assert!(nread("bop insert k 1 2 pipe"));
+        assert!(nread("mop delete k 3 1"));
+        assert!(!nread("mop delete k 0 0"));
+        assert!(!nread("bop update k 1 -1"));
+        assert!(!nread("get k"));
+        assert!(!nread("lop delete k 0"));
+    }
 
-    if let Some(first_line) = lines.next() {
-        // 첫 줄은 띄어쓰기 기준으로 split
-        let first_split: Vec<&str> = first_line.split_whitespace().collect();
-        println!("First line split: {:?}", first_split);
+    #[test]
+    fn matches_arguments() {
+        let m = tree().parse("cas k 3 0 5 42 noreply").unwrap().unwrap();
+        assert_eq!(m.arg("key"), Some("k"));
+        assert_eq!(m.arg("flags"), Some("3"));
+        assert_eq!(m.arg("bytes"), Some("5"));
+        assert_eq!(m.arg("cas_unique"), Some("42"));
+        assert_eq!(m.arg("delay"), None);
     }
 
-    // 나머지 줄은 그대로 출력
-    for line in lines {
-        println!("Remaining line: {}", line);
+    #[test]
+    fn completes_keywords() {
+        let tree = tree();
+        let complete = |words: &[&str]| {
+            let mut out = tree.complete(words);
+            out.sort();
+            out
+        };
+        assert!(complete(&[]).contains(&"get".to_string()));
+        assert_eq!(complete(&["lop"]), ["create", "delete", "get", "insert"]);
+        assert_eq!(complete(&["stats", "detail"]), ["dump", "off", "on"]);
+        assert_eq!(complete(&["lop", "get", "k", "0"]), ["delete", "drop"]);
+        assert!(complete(&["set", "k", "0", "0", "2"]).contains(&"noreply".to_string()));
+        assert!(complete(&["nosuch"]).is_empty());
     }
 }
@@ -0,0 +1,11 @@
+//! ARCUS/memcached client code shared by the `arcus-cli` binary: transports
+//! (TCP, TLS, UDP, Unix sockets), SASL, key routing, the command grammar and a
+//! typed request API in [`client`], and replies taken apart in [`response`].
+
+/* Functions end in an explicit `return`, as everywhere in this code. */
+#![allow(clippy::needless_return)]
+
+pub mod client;
+pub mod connect;
+pub mod helper;
+pub mod response;
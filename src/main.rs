@@ -1,18 +1,53 @@
-mod helper;
-mod connect;
+/* Functions end in an explicit `return`, as everywhere in this code. */
+#![allow(clippy::needless_return)]
 
-use std::{thread, time};
+mod batch;
+mod bench;
+mod output;
+mod pipeline;
+mod stats;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, IsTerminal};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use rustyline::history::DefaultHistory;
 use rustyline::Editor;
 use rustyline::error::ReadlineError;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use arcus_cli::{connect, helper};
+use connect::cluster::Cluster;
+use connect::credentials::Credentials;
+use connect::error::{ConnectError, Error};
+use connect::event::Event;
+use connect::stream::{Reconnect, Timeouts};
+use connect::udp::Framing;
+use output::{Format, Output};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum UdpFraming {
+    Header,
+    Raw,
+    Probe,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate load and report throughput and latency percentiles
+    Bench(bench::Options),
+}
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = "\
+Exit status: 0 success, 1 error reply (with --fail-fast) or I/O error, 2 usage error,
+3 host did not resolve, 4 connection refused, 5 SASL authentication failed, 6 protocol error,
+7 connecting timed out, 8 TLS handshake failed")]
 struct Args {
-    /// Host name or IP or Unix path
-    #[arg(long, default_value_t = String::from("127.0.0.1"))]
-    host: String,
+    /// Host name or IP (optionally with :port) or Unix path; repeat to spread keys over several nodes
+    #[arg(long, default_value = "127.0.0.1")]
+    host: Vec<String>,
 
     /// Port Number
     #[arg(short, long, default_value_t = 11211)]
@@ -22,6 +57,10 @@ struct Args {
     #[clap(long, action=ArgAction::SetTrue)]
     udp: bool,
 
+    /// Local address (IP or IP:port) to send UDP requests from
+    #[arg(long, requires = "udp", value_parser = bind_address)]
+    bind: Option<SocketAddr>,
+
     /// Request ID for UDP
     #[arg(long, default_value_t = 1)]
     req_id: u16,
@@ -30,18 +69,284 @@ struct Args {
     #[arg(long, action=ArgAction::SetTrue)]
     unix: bool,
 
-    /// Timeout(μs)
+    /// UDP datagram framing; "probe" asks the server which one it expects
+    #[arg(long, value_enum, default_value_t = UdpFraming::Header)]
+    udp_framing: UdpFraming,
+
+    /// Timeout(ms) for UDP replies
     #[arg(short, long, default_value_t = 100)]
     timeout: u64,
 
+    /// Give up connecting to a TCP server after this long(ms)
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    connect_timeout: Option<u64>,
+
+    /// Give up waiting for a TCP or Unix socket reply after this long(ms)
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    read_timeout: Option<u64>,
+
+    /// Give up sending a TCP or Unix socket request after this long(ms)
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    write_timeout: Option<u64>,
+
+    /// Reconnect attempts after a TCP or Unix socket connection drops
+    #[arg(long, default_value_t = 3, value_name = "N")]
+    reconnect: u32,
+
+    /// Wait(ms) before the first reconnect attempt; doubles with every attempt
+    #[arg(long, default_value_t = 100, value_name = "MS")]
+    backoff: u64,
+
     /// Authenticate with sasl
     #[clap(long, action=ArgAction::SetTrue)]
     sasl: bool,
+
+    /// SASL username
+    #[arg(long, requires = "sasl")]
+    user: Option<String>,
+
+    /// Environment variable holding the SASL password
+    #[arg(long, requires = "sasl", value_name = "VAR")]
+    password_env: Option<String>,
+
+    /// File holding the SASL password
+    #[arg(long, requires = "sasl", conflicts_with = "password_env")]
+    password_file: Option<PathBuf>,
+
+    /// SASL mechanism to use (PLAIN, SCRAM-SHA-256, ...) instead of the best one the server offers
+    #[arg(long, requires = "sasl", value_name = "MECH")]
+    sasl_mech: Option<String>,
+
+    /// Authorization identity to act on behalf of
+    #[arg(long, requires = "sasl")]
+    authzid: Option<String>,
+
+    /// Encrypt TCP connections with TLS
+    #[clap(long, action=ArgAction::SetTrue, conflicts_with_all = ["udp", "unix"])]
+    tls: bool,
+
+    /// CA certificates (PEM) to verify the server with, instead of the bundled roots
+    #[arg(long, requires = "tls", conflicts_with_all = ["udp", "unix"])]
+    cacert: Option<PathBuf>,
+
+    /// Client certificate chain (PEM) for mutual TLS
+    #[arg(long, requires_all = ["tls", "key"], conflicts_with_all = ["udp", "unix"])]
+    cert: Option<PathBuf>,
+
+    /// Private key (PEM) of the client certificate
+    #[arg(long, requires_all = ["tls", "cert"], conflicts_with_all = ["udp", "unix"])]
+    key: Option<PathBuf>,
+
+    /// Skip verification of the server certificate
+    #[clap(long, action=ArgAction::SetTrue, requires = "tls", conflicts_with_all = ["udp", "unix"])]
+    insecure: bool,
+
+    /// ZooKeeper ensemble (host:port[,host:port...]) to discover the cache nodes from
+    #[arg(long, requires = "service_code", conflicts_with_all = ["host", "udp", "unix"])]
+    zookeeper: Option<String>,
+
+    /// ARCUS service code to look up under /arcus/cache_list
+    #[arg(long, requires = "zookeeper")]
+    service_code: Option<String>,
+
+    /// Run a command and exit (may be repeated)
+    #[arg(short, long, conflicts_with = "file")]
+    exec: Vec<String>,
+
+    /// Run the commands in a file and exit (stdin is read the same way when piped)
+    #[arg(short, long)]
+    file: Option<PathBuf>,
+
+    /// Stop with a non-zero exit status at the first ERROR, CLIENT_ERROR or SERVER_ERROR
+    #[clap(long, action=ArgAction::SetTrue)]
+    fail_fast: bool,
+
+    /// Send the commands of --exec, --file or stdin in pipelines of up to N requests
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pipeline: Option<u32>,
+
+    /// How to print replies; "\format" changes it during a session
+    #[arg(long, value_enum, default_value_t = Format::Raw)]
+    output: Format,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/* Lines starting with a backslash are for the CLI itself; `None` for any other line. */
+fn meta(line: &str, cluster: &mut Cluster, output: &mut Output) -> Option<Result<(), String>> {
+    let words: Vec<&str> = line.strip_prefix('\\')?.split_whitespace().collect();
+    return Some(match words.as_slice() {
+        ["stats", args @ ..] => stats::run(cluster, args),
+        ["format"] => { println!("{}", output.format); Ok(()) },
+        ["format", format] => Format::from_str(format, true)
+                                     .map(|format| output.format = format)
+                                     .map_err(|_| format!("unknown format \"{}\" (raw, pretty or json)", format)),
+        [command, ..] => Err(format!("unknown meta-command \\{}", command)),
+        [] => Err("missing meta-command".to_string()),
+    });
+}
+
+/* "host" gets the default port; "host:port" and "[v6]:port" keep their own. */
+fn address(host: &str, port: u16) -> String {
+    match host.rsplit_once(':') {
+        Some((name, p)) if p.parse::<u16>().is_ok() && (!name.contains(':') || name.ends_with(']')) =>
+            host.to_string(),
+        _ if host.contains(':') => format!("[{}]:{}", host, port),
+        _ => format!("{}:{}", host, port),
+    }
+}
+
+/* "ip" binds to any free port; "ip:port" and "[v6]:port" to that one. */
+fn bind_address(addr: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = addr.parse::<SocketAddr>() { return Ok(addr); }
+    return addr.trim_start_matches('[').trim_end_matches(']')
+               .parse::<IpAddr>()
+               .map(|ip| SocketAddr::new(ip, 0))
+               .map_err(|_| format!("invalid bind address \"{}\"", addr));
 }
 
 fn main() -> rustyline::Result<()> {
     let args = Args::parse();
-    let timeout = time::Duration::from_micros(args.timeout);
+    let (name, addrs) = if let Some(ensemble) = args.zookeeper.as_deref() {
+        let service_code = args.service_code.clone().unwrap();
+        match connect::zookeeper::cache_list(ensemble, &service_code) {
+            Ok(nodes) if !nodes.is_empty() => (service_code, nodes),
+            Ok(_) => {
+                eprintln!("ERROR: No cache nodes for service code {}", service_code);
+                std::process::exit(1);
+            },
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                let resolve = err.get_ref()
+                                 .and_then(|inner| inner.downcast_ref::<ConnectError>())
+                                 .is_some_and(|inner| matches!(inner, ConnectError::Resolve(..)));
+                std::process::exit(if resolve { connect::error::EXIT_RESOLVE } else { 1 });
+            }
+        }
+    } else {
+        let addrs: Vec<String> = args.host.iter().map(|host| {
+            if args.unix { host.clone() } else { address(host, args.port) }
+        }).collect();
+        (addrs.join(","), addrs)
+    };
+
+    let creds = if args.sasl {
+        match Credentials::resolve(args.user.clone(), args.password_env.as_deref(), args.password_file.as_deref()) {
+            Ok(creds) => Some(Credentials { authzid: args.authzid.clone(), mech: args.sasl_mech.clone(), ..creds }),
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let tls = if args.tls {
+        match connect::tls::config(args.cacert.as_deref(), args.cert.as_deref(), args.key.as_deref(), args.insecure) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let build = || {
+        let nodes = addrs.iter().map(|addr| if args.unix {
+            connect::Transport::UNIX(addr.clone(), Default::default())
+        } else if args.udp {
+            connect::Transport::UDP(addr.clone(), Default::default())
+        } else {
+            connect::Transport::TCP(addr.clone(), Default::default())
+        });
+        let mut cluster = Cluster::new(name.clone(), nodes.collect());
+        cluster.setting(args.req_id, args.timeout, creds.clone());
+        cluster.log(Arc::new(|event: &Event| match event.is_error() {
+            true => eprintln!("ERROR: {}", event),
+            false => eprintln!("{}", event),
+        }));
+        cluster.retry(Reconnect { attempts: args.reconnect, backoff: Duration::from_millis(args.backoff) });
+        cluster.timeouts(Timeouts {
+            connect: args.connect_timeout.map(Duration::from_millis),
+            read: args.read_timeout.map(Duration::from_millis),
+            write: args.write_timeout.map(Duration::from_millis),
+        });
+        if let Some(addr) = args.bind {
+            cluster.bind(addr);
+        }
+        if let Some(config) = tls.as_ref() {
+            cluster.secure(config.clone());
+        }
+        match args.udp_framing {
+            UdpFraming::Header => cluster.framing(Framing::Header),
+            UdpFraming::Raw => cluster.framing(Framing::Raw),
+            UdpFraming::Probe => (),
+        }
+        cluster
+    };
+    let mut cluster = build();
+    /* Interactive sessions start anyway while a server is down, and batch runs
+       while at least one is up. Other failures end the run at once. */
+    let batch = args.command.is_some() || !args.exec.is_empty() || args.file.is_some() || !io::stdin().is_terminal();
+    let failed = cluster.connect();
+    let all_down = failed.len() == cluster.len();
+    for (_, err) in failed.iter() {
+        eprintln!("ERROR: {}", err);
+        match err {
+            Error::Connect(ConnectError::Refused(..) | ConnectError::Timeout(..)) if !batch || !all_down => (),
+            _ => std::process::exit(err.exit_code()),
+        }
+    }
+    if let UdpFraming::Probe = args.udp_framing {
+        for (node, res) in cluster.probe() {
+            match res {
+                Ok(framing) => eprintln!("UDP framing of {}: {}", node, framing),
+                Err(err) => {
+                    eprintln!("ERROR: {}: {}", node, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    if let Some(Command::Bench(opts)) = args.command.as_ref() {
+        /* Every further connection sets up the way the first one did, but quietly. */
+        let open = || -> Result<Cluster, Error> {
+            let mut cluster = build();
+            if let Some((_, err)) = cluster.connect().into_iter().next() {
+                return Err(err);
+            }
+            if let UdpFraming::Probe = args.udp_framing {
+                for (_, res) in cluster.probe() {
+                    res?;
+                }
+            }
+            Ok(cluster)
+        };
+        let code = bench::run(opts, cluster, &open);
+        if code != 0 { std::process::exit(code); }
+        return Ok(());
+    }
+
+    let mut output = Output { format: args.output };
+    if batch {
+        let h = helper::MyHelper::new();
+        let depth = args.pipeline.unwrap_or(1) as usize;
+        let code = match args.file {
+            _ if !args.exec.is_empty() =>
+                batch::run(&mut cluster, &h, Cursor::new(args.exec.join("\n")), &mut output, args.fail_fast, depth),
+            Some(path) => match File::open(&path) {
+                Ok(file) => batch::run(&mut cluster, &h, BufReader::new(file), &mut output, args.fail_fast, depth),
+                Err(err) => { eprintln!("ERROR: {}: {}", path.display(), err); 1 }
+            },
+            None => batch::run(&mut cluster, &h, io::stdin().lock(), &mut output, args.fail_fast, depth),
+        };
+        if code != 0 { std::process::exit(code); }
+        return Ok(());
+    }
+
     let h = helper::MyHelper::new();
     let mut rl: Editor<helper::MyHelper, DefaultHistory> = Editor::new()?;
     rl.set_helper(Some(h));
@@ -51,26 +356,31 @@ fn main() -> rustyline::Result<()> {
         std::process::exit(1);
     }
 
-    let mut transport = if args.unix {
-        connect::Transport::UNIX(args.host, Default::default())
-    } else if args.udp {
-        connect::Transport::UDP(format!("{}:{}", args.host, args.port), Default::default())
-    } else {
-        connect::Transport::TCP(format!("{}:{}", args.host, args.port), Default::default())
-    };
-
-    transport.setting(args.req_id, args.timeout, args.sasl);
-    transport.write("".to_string());
     loop {
-        let readline = rl.readline("");
+        let readline = rl.readline(&cluster.prompt());
         match readline {
             Ok(line) => {
                 let _ = rl.add_history_entry(line.as_str());
                 if line == "quit" { break }
-                transport.write(line);
+                if let Some(res) = meta(&line, &mut cluster, &mut output) {
+                    if let Err(err) = res { eprintln!("ERROR: {}", err); }
+                    continue;
+                }
+                let h = rl.helper().unwrap();
+                if helper::is_pipeline(&line) {
+                    match h.pipeline(&line) {
+                        Ok(reqs) => { let _ = pipeline::run(&mut cluster, reqs, &output); },
+                        Err(err) => eprintln!("ERROR: {}", err),
+                    }
+                    continue;
+                }
+                match h.request(&line) {
+                    Ok(req) => output.print(&req, &cluster.write(req.clone())),
+                    Err(err) => eprintln!("ERROR: {}", err),
+                }
             },
             Err(ReadlineError::Interrupted) => { break },
-            Err(ReadlineError::Eof) => { thread::sleep(timeout); break },
+            Err(ReadlineError::Eof) => { break },
             Err(err) => { eprintln!("ERROR: {:?}", err); break }
         }
     }
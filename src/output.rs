@@ -0,0 +1,325 @@
+use std::io::{self, Write};
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use arcus_cli::client::Item;
+use arcus_cli::connect::cluster::Replies;
+use arcus_cli::response::{self, Collection, Element, Response};
+
+/// How replies are printed.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// As the server sent them
+    Raw,
+    /// Aligned tables and name/value lists
+    Pretty,
+    /// One JSON object per reply
+    Json,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self { Format::Raw => "raw", Format::Pretty => "pretty", Format::Json => "json" })
+    }
+}
+
+pub struct Output {
+    pub format: Format,
+}
+
+impl Output {
+    /// Prints the replies to `req`, labelled with their node when there are
+    /// several. Replies that never came are reported on stderr.
+    pub fn print(&self, req: &[u8], replies: &Replies) {
+        let mut stdout = io::stdout().lock();
+        for (node, reply) in replies {
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(err) => { eprintln!("ERROR: {}", err); continue }
+            };
+            let _ = match self.format {
+                Format::Raw => {
+                    if replies.len() > 1 { let _ = writeln!(stdout, "[{}]", node); }
+                    stdout.write_all(reply)
+                },
+                Format::Pretty => {
+                    if replies.len() > 1 { let _ = writeln!(stdout, "[{}]", node); }
+                    stdout.write_all(pretty(req, &response::parse(req, reply)).as_bytes())
+                },
+                Format::Json => {
+                    let mut value = json(&response::parse(req, reply));
+                    if let (true, Value::Object(map)) = (replies.len() > 1, &mut value) {
+                        map.insert("node".to_string(), json!(node));
+                    }
+                    writeln!(stdout, "{}", value)
+                },
+            };
+        }
+        let _ = stdout.flush();
+    }
+}
+
+/* Data as text, with control characters escaped so they cannot upset the terminal. */
+fn text(data: &[u8]) -> String {
+    let mut out = String::new();
+    for c in String::from_utf8_lossy(data).chars() {
+        if c.is_control() { out.extend(c.escape_default()) } else { out.push(c) }
+    }
+    return out;
+}
+
+/* Rows under a header, each column as wide as its widest cell. Columns of
+   numbers are aligned to the right, except the last one, which holds data. */
+pub fn table(head: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = head.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let numeric: Vec<bool> = (0..head.len()).map(|i| {
+        i + 1 < head.len() && rows.iter().all(|row| row[i].is_empty() || row[i].parse::<i64>().is_ok())
+    }).collect();
+    let mut out = String::new();
+    let head: Vec<String> = head.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&head).chain(rows) {
+        let cells: Vec<String> = row.iter().zip(widths.iter()).zip(numeric.iter())
+                                    .map(|((cell, &width), &numeric)| match numeric {
+                                        true => format!("{:>width$}", cell, width = width),
+                                        false => format!("{:<width$}", cell, width = width),
+                                    })
+                                    .collect();
+        out += cells.join("  ").trim_end();
+        out.push('\n');
+    }
+    return out;
+}
+
+fn pretty_items(items: &[(String, Item)]) -> String {
+    let cas = items.iter().any(|(_, item)| item.cas.is_some());
+    let mut head = vec!["key", "flags", "bytes"];
+    if cas { head.push("cas"); }
+    head.push("value");
+    let rows: Vec<Vec<String>> = items.iter().map(|(key, item)| {
+        let mut row = vec![key.clone(), item.flags.to_string(), item.data.len().to_string()];
+        if cas { row.push(item.cas.map_or(String::new(), |c| c.to_string())); }
+        row.push(text(&item.data));
+        row
+    }).collect();
+    return table(&head, &rows) + &format!("({})\n", plural(items.len(), "item"));
+}
+
+fn plural(n: usize, what: &str) -> String {
+    format!("{} {}{}", n, what, if n == 1 { "" } else { "s" })
+}
+
+/* The other spelling of a bkey: hex for a number, and a number for hex that fits in 64 bits. */
+fn bkey_alt(bkey: &str) -> String {
+    if let Some(hex) = bkey.strip_prefix("0x") {
+        return match u64::from_str_radix(hex, 16) {
+            Ok(n) if hex.len() <= 16 => n.to_string(),
+            _ => String::new(),
+        };
+    }
+    return bkey.parse::<u64>().map_or(String::new(), |n| format!("0x{:X}", n));
+}
+
+/* What a closing line other than END says about the elements above it. */
+fn status_note(status: &str) -> String {
+    let note = match status {
+        "END" | "OK" => return status.to_string(),
+        "TRIMMED" => "part of the range was trimmed away earlier; elements may be missing",
+        "DELETED" => "the elements were deleted",
+        "DELETED_DROPPED" => "the elements were deleted and the emptied collection dropped",
+        "DUPLICATED" => "some bkeys appear under more than one key",
+        "DUPLICATED_TRIMMED" => "some bkeys appear under more than one key, and some keys were trimmed",
+        _ => return status.to_string(),
+    };
+    return format!("{} ({})", status, note);
+}
+
+/* "<from>[..<to>]" as its first number and the direction it runs in. A
+   negative list index counts from the end, so "0..-1" runs forward. */
+fn range_start(range: &str) -> Option<(i64, i64)> {
+    let (from, to) = range.split_once("..").unwrap_or((range, range));
+    let (from, to) = (from.parse::<i64>().ok()?, to.parse::<i64>().ok()?);
+    let forward = if (from < 0) == (to < 0) { from <= to } else { from >= 0 };
+    return Some((from, if forward { 1 } else { -1 }));
+}
+
+/* Header and values of the column that numbers the elements: the list index,
+   the b+tree position, or just the row number. */
+fn numbering(req: &[u8], collection: &Collection) -> (&'static str, Vec<i64>) {
+    let line = String::from_utf8_lossy(req.split(|&b| b == b'\n').next().unwrap_or(b""));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let count = collection.elements.len() as i64;
+    let start = match (words.as_slice(), collection.position, collection.index) {
+        (["lop", "get", _, range, ..], _, _) => range_start(range).map(|start| ("index", start)),
+        /* Positions count in the order the request asked for, as do the elements. */
+        (["bop", "gbp", _, _, range, ..], _, _) => range_start(range).map(|start| ("position", start)),
+        (["bop", "pwg", ..], Some(position), Some(index)) => Some(("position", (position as i64 - index as i64, 1))),
+        _ => None,
+    };
+    return match start {
+        Some((name, (from, step))) => (name, (0..count).map(|i| from + step * i).collect()),
+        None => ("#", (1..=count).collect()),
+    };
+}
+
+/* One row per element; columns that no element fills are left out. */
+fn pretty_elements(elements: &[Element], numbers: Option<(&str, Vec<i64>)>) -> String {
+    let mut head = Vec::new();
+    if let Some((name, _)) = numbers.as_ref() { head.push(*name); }
+    let keyed = elements.iter().any(|e| e.key.is_some());
+    if keyed { head.extend(["key", "flags"]); }
+    let fields = elements.iter().any(|e| e.field.is_some());
+    if fields { head.push("field"); }
+    let bkeys = elements.iter().any(|e| e.bkey.is_some());
+    let hex = elements.iter().any(|e| e.bkey.as_deref().is_some_and(|b| b.starts_with("0x")));
+    if bkeys { head.extend(["bkey", if hex { "(dec)" } else { "(hex)" }]); }
+    let eflags = elements.iter().any(|e| e.eflag.is_some());
+    if eflags { head.push("eflag"); }
+    head.extend(["bytes", "value"]);
+
+    let rows: Vec<Vec<String>> = elements.iter().enumerate().map(|(i, e)| {
+        let mut row = Vec::new();
+        if let Some((_, numbers)) = numbers.as_ref() { row.push(numbers[i].to_string()); }
+        if keyed {
+            row.push(e.key.clone().unwrap_or_default());
+            row.push(e.flags.map_or(String::new(), |f| f.to_string()));
+        }
+        if fields { row.push(e.field.clone().unwrap_or_default()); }
+        if bkeys {
+            let bkey = e.bkey.clone().unwrap_or_default();
+            row.push(bkey_alt(&bkey));
+            row.insert(row.len() - 1, bkey);
+        }
+        if eflags { row.push(e.eflag.clone().unwrap_or_default()); }
+        row.push(e.data.len().to_string());
+        row.push(text(&e.data));
+        row
+    }).collect();
+    return table(&head, &rows);
+}
+
+fn pretty_collection(req: &[u8], collection: &Collection) -> String {
+    let mut out = pretty_elements(&collection.elements, Some(numbering(req, collection)));
+    out += &format!("({}, flags {}", plural(collection.elements.len(), "element"), collection.flags);
+    let bkeys: Vec<&str> = collection.elements.iter().filter_map(|e| e.bkey.as_deref()).collect();
+    if let (Some(first), Some(last)) = (bkeys.first(), bkeys.last()) {
+        out += &format!(", bkeys {}..{}", first, last);
+    }
+    if let (Some(position), Some(index)) = (collection.position, collection.index) {
+        out += &format!(", position {} at row {}", position, index + 1);
+    }
+    return out + &format!(") {}\n", status_note(&collection.status));
+}
+
+fn pretty(req: &[u8], resp: &Response) -> String {
+    return match resp {
+        Response::Status(line) | Response::Error(line) => format!("{}\n", line),
+        Response::Number(Some(name), n) => format!("{}: {}\n", name.to_lowercase(), n),
+        Response::Number(None, n) => format!("{}\n", n),
+        Response::Items(items) => pretty_items(items),
+        Response::Collection(collection) => pretty_collection(req, collection),
+        Response::Collections(keys) => {
+            let mut out = String::new();
+            for (key, status, collection) in keys {
+                out += &match collection {
+                    Some(collection) => format!("{}:\n{}", key, pretty_collection(req, collection)),
+                    None => format!("{}: {}\n", key, status),
+                };
+            }
+            let found = keys.iter().filter(|(_, _, c)| c.is_some()).count();
+            out + &format!("({}: {} with elements, {} without)\n", plural(keys.len(), "key"), found, keys.len() - found)
+        },
+        Response::Sorted { elements, missed, trimmed, status } => {
+            let numbers = ("#", (1..=elements.len() as i64).collect());
+            let mut out = pretty_elements(elements, Some(numbers));
+            if !missed.is_empty() {
+                let rows: Vec<Vec<String>> = missed.iter()
+                                                   .map(|(key, cause)| vec![key.clone(), cause.clone().unwrap_or_default()])
+                                                   .collect();
+                out += &table(&["missed key", "cause"], &rows);
+            }
+            if !trimmed.is_empty() {
+                let rows: Vec<Vec<String>> = trimmed.iter().map(|(key, bkey)| vec![key.clone(), bkey.clone()]).collect();
+                out += &table(&["trimmed key", "last bkey"], &rows);
+            }
+            let keys: std::collections::BTreeSet<&str> = elements.iter().filter_map(|e| e.key.as_deref()).collect();
+            out + &format!("({} from {}, {} missed, {} trimmed) {}\n", plural(elements.len(), "element"),
+                           plural(keys.len(), "key"), missed.len(), trimmed.len(), status_note(status))
+        },
+        Response::Stats(pairs) | Response::Attributes(pairs) => {
+            let width = pairs.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            pairs.iter().map(|(name, value)| format!("{:<width$}  {}\n", name, value, width = width)).collect()
+        },
+        Response::Lines(lines) => lines.iter().map(|line| format!("{}\n", line)).collect(),
+    };
+}
+
+/* Numbers as numbers, so that jq can compare and add them. */
+fn scalar(value: &str) -> Value {
+    if let Ok(n) = value.parse::<u64>() { return json!(n); }
+    if let Ok(n) = value.parse::<i64>() { return json!(n); }
+    if let Some(n) = value.parse::<f64>().ok().filter(|n| n.is_finite()) { return json!(n); }
+    return json!(value);
+}
+
+fn json_element(e: &Element) -> Value {
+    let mut map = Map::new();
+    if let Some(key) = e.key.as_ref() { map.insert("key".to_string(), json!(key)); }
+    if let Some(flags) = e.flags { map.insert("flags".to_string(), json!(flags)); }
+    if let Some(field) = e.field.as_ref() { map.insert("field".to_string(), json!(field)); }
+    if let Some(bkey) = e.bkey.as_ref() { map.insert("bkey".to_string(), json!(bkey)); }
+    if let Some(eflag) = e.eflag.as_ref() { map.insert("eflag".to_string(), json!(eflag)); }
+    map.insert("value".to_string(), json!(String::from_utf8_lossy(&e.data)));
+    return Value::Object(map);
+}
+
+fn json_collection(c: &Collection) -> Value {
+    let mut value = json!({
+        "flags": c.flags,
+        "elements": c.elements.iter().map(json_element).collect::<Vec<_>>(),
+        "status": c.status,
+    });
+    if let (Some(position), Some(index)) = (c.position, c.index) {
+        value["position"] = json!(position);
+        value["index"] = json!(index);
+    }
+    return value;
+}
+
+fn json_pairs(pairs: &[(String, String)]) -> Value {
+    Value::Object(pairs.iter().map(|(name, value)| (name.clone(), scalar(value))).collect())
+}
+
+fn json(resp: &Response) -> Value {
+    return match resp {
+        Response::Status(line) => json!({ "status": line }),
+        Response::Error(line) => json!({ "error": line }),
+        Response::Number(Some(name), n) => json!({ name.to_lowercase(): n }),
+        Response::Number(None, n) => json!({ "value": n }),
+        Response::Items(items) => json!({
+            "items": items.iter().map(|(key, item)| {
+                let mut value = json!({ "flags": item.flags, "value": String::from_utf8_lossy(&item.data) });
+                if let Some(cas) = item.cas { value["cas"] = json!(cas); }
+                (key.clone(), value)
+            }).collect::<Map<_, _>>(),
+        }),
+        Response::Collection(collection) => json_collection(collection),
+        Response::Collections(keys) => json!({
+            "keys": keys.iter().map(|(key, status, collection)| {
+                (key.clone(), collection.as_ref().map_or_else(|| json!({ "status": status }), json_collection))
+            }).collect::<Map<_, _>>(),
+        }),
+        Response::Sorted { elements, missed, trimmed, status } => json!({
+            "elements": elements.iter().map(json_element).collect::<Vec<_>>(),
+            "missed_keys": missed.iter().map(|(key, cause)| json!({ "key": key, "cause": cause })).collect::<Vec<_>>(),
+            "trimmed_keys": trimmed.iter().map(|(key, bkey)| json!({ "key": key, "bkey": bkey })).collect::<Vec<_>>(),
+            "status": status,
+        }),
+        Response::Stats(pairs) => json!({ "stats": json_pairs(pairs) }),
+        Response::Attributes(pairs) => json!({ "attributes": json_pairs(pairs) }),
+        Response::Lines(lines) => json!({ "lines": lines }),
+    };
+}
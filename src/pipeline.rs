@@ -0,0 +1,36 @@
+use std::time::Instant;
+use crate::connect::reply;
+use crate::connect::cluster::Cluster;
+use crate::output::Output;
+
+/// Sends `reqs` as one pipeline and prints the replies of each request in
+/// order, then a summary line with the throughput on stderr. Returns whether
+/// any reply was an error, or the exit status of the first request that got
+/// no reply at all.
+pub fn run(cluster: &mut Cluster, reqs: Vec<Vec<u8>>, output: &Output) -> Result<bool, i32> {
+    let count = reqs.len();
+    let started = Instant::now();
+    let replies = cluster.pipeline(reqs.clone());
+    let elapsed = started.elapsed();
+
+    let mut errors = 0;
+    let mut lost = None;
+    for (req, replies) in reqs.iter().zip(replies.iter()) {
+        output.print(req, replies);
+        for (_, resp) in replies {
+            match resp {
+                Ok(resp) if reply::is_error(resp) => errors += 1,
+                Ok(_) => (),
+                Err(err) => { lost.get_or_insert(err.exit_code()); },
+            }
+        }
+    }
+    let lost_count = replies.iter().filter(|r| r.iter().any(|(_, resp)| resp.is_err())).count();
+    let rate = count as f64 / elapsed.as_secs_f64().max(1e-9);
+    eprintln!("pipeline: {} requests, {} error replies, {} without reply in {:?} ({:.0} req/s)",
+              count, errors, lost_count, elapsed, rate);
+    return match lost {
+        Some(code) => Err(code),
+        None => Ok(errors > 0),
+    };
+}
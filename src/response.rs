@@ -0,0 +1,380 @@
+//! Replies taken apart into their fields, for output other than the raw text.
+
+use crate::client::Item;
+use crate::connect::reply::{self, Shape};
+
+/// One element of a collection reply. Which fields are set depends on the
+/// collection: lists and sets only carry data, maps a field, b+trees a bkey
+/// and maybe an eflag, and `bop smget` also the key and flags it came from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Element {
+    pub key: Option<String>,
+    pub flags: Option<u32>,
+    pub field: Option<String>,
+    pub bkey: Option<String>,
+    pub eflag: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// The elements of one collection, as `lop/sop/mop/bop get`, `bop gbp` and
+/// `bop pwg` return them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Collection {
+    pub flags: u32,
+    /// Position of the element asked for; `bop pwg` only.
+    pub position: Option<u64>,
+    /// Index of that element among `elements`; `bop pwg` only.
+    pub index: Option<u64>,
+    pub elements: Vec<Element>,
+    /// The closing line: `END`, `TRIMMED`, `DELETED` or `DELETED_DROPPED`.
+    pub status: String,
+}
+
+/// A reply by what it holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// A one line status, such as `STORED`, `NOT_FOUND` or `END`.
+    Status(String),
+    /// `ERROR`, `CLIENT_ERROR` or `SERVER_ERROR` with its message.
+    Error(String),
+    /// The value of `incr`/`decr`, or of `COUNT=` and `POSITION=` with their name.
+    Number(Option<String>, u64),
+    /// `get`, `gets`, `mget` and `mgets` items by key, in reply order.
+    Items(Vec<(String, Item)>),
+    Collection(Collection),
+    /// `bop mget`: the status of each key, with its elements if it has any.
+    Collections(Vec<(String, String, Option<Collection>)>),
+    /// `bop smget`: the elements in bkey order, the keys that were missed
+    /// (with the cause, if given) and the keys that were trimmed (with the
+    /// last bkey kept).
+    Sorted {
+        elements: Vec<Element>,
+        missed: Vec<(String, Option<String>)>,
+        trimmed: Vec<(String, String)>,
+        status: String,
+    },
+    /// `STAT <name> <value>` lines.
+    Stats(Vec<(String, String)>),
+    /// `ATTR <name>=<value>` lines.
+    Attributes(Vec<(String, String)>),
+    /// Any other reply, line by line.
+    Lines(Vec<String>),
+}
+
+/// Reads `reply`, the reply to `req`, into a `Response`. Replies that do not
+/// read as the request calls for come back as `Lines`.
+pub fn parse(req: &[u8], reply: &[u8]) -> Response {
+    let line = String::from_utf8_lossy(req.split(|&b| b == b'\n').next().unwrap_or(b""));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let lines = || Response::Lines(String::from_utf8_lossy(reply).lines().map(str::to_string).collect());
+    if reply::is_error(reply) {
+        return Response::Error(first_line(reply).0);
+    }
+    let parsed = match words.as_slice() {
+        ["get" | "gets" | "mget" | "mgets", ..] => items(reply),
+        ["lop" | "sop", "get", ..] => collection(reply, Shape::List),
+        ["mop", "get", ..] => collection(reply, Shape::Map),
+        ["bop", "get" | "gbp" | "pwg", ..] => collection(reply, Shape::Btree),
+        ["bop", "mget", ..] => collections(reply),
+        ["bop", "smget", ..] => sorted(reply),
+        ["stats", ..] => pairs(reply, "STAT ", ' ').map(Response::Stats),
+        ["getattr", ..] => pairs(reply, "ATTR ", '=').map(Response::Attributes),
+        _ => None,
+    };
+    if let Some(parsed) = parsed { return parsed; }
+
+    let (first, rest) = first_line(reply);
+    if !rest.is_empty() { return lines(); }
+    if let Ok(n) = first.parse::<u64>() { return Response::Number(None, n); }
+    if let Some((name, n)) = first.split_once('=').and_then(|(name, n)| Some((name, n.parse::<u64>().ok()?))) {
+        return Response::Number(Some(name.to_string()), n);
+    }
+    return Response::Status(first);
+}
+
+/// Splits off the first line of `reply`, without its "\r\n".
+pub(crate) fn first_line(reply: &[u8]) -> (String, &[u8]) {
+    let end = reply.windows(2).position(|w| w == b"\r\n").unwrap_or(reply.len());
+    let line = String::from_utf8_lossy(&reply[..end]).to_string();
+    return (line, &reply[(end + 2).min(reply.len())..]);
+}
+
+/* Splits the next space-separated word off `rest`. */
+fn word(rest: &mut &[u8]) -> Option<String> {
+    let end = rest.iter().position(|&b| b == b' ' || b == b'\r').unwrap_or(rest.len());
+    if end == 0 { return None; }
+    let word = String::from_utf8_lossy(&rest[..end]).to_string();
+    *rest = &rest[end..];
+    if rest.first() == Some(&b' ') { *rest = &rest[1..]; }
+    return Some(word);
+}
+
+/* Splits `len` bytes of data and the "\r\n" after them off `rest`. */
+fn data(rest: &mut &[u8], len: usize) -> Option<Vec<u8>> {
+    if rest.len() < len + 2 || &rest[len..len + 2] != b"\r\n" { return None; }
+    let data = rest[..len].to_vec();
+    *rest = &rest[len + 2..];
+    return Some(data);
+}
+
+fn element(rest: &mut &[u8], shape: Shape) -> Option<Element> {
+    let mut element = Element::default();
+    if shape == Shape::Sorted {
+        element.key = Some(word(rest)?);
+        element.flags = Some(word(rest)?.parse().ok()?);
+    }
+    if shape == Shape::Map {
+        element.field = Some(word(rest)?);
+    }
+    let mut bytes = word(rest)?;
+    if matches!(shape, Shape::Btree | Shape::Sorted) {
+        element.bkey = Some(bytes);
+        bytes = word(rest)?;
+        if bytes.starts_with("0x") {
+            element.eflag = Some(bytes);
+            bytes = word(rest)?;
+        }
+    }
+    element.data = data(rest, bytes.parse().ok()?)?;
+    return Some(element);
+}
+
+fn items(reply: &[u8]) -> Option<Response> {
+    let mut items = Vec::new();
+    let mut rest = reply;
+    loop {
+        let (line, next) = first_line(rest);
+        rest = next;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["END"] if rest.is_empty() => return Some(Response::Items(items)),
+            ["VALUE", key, flags, bytes, cas @ ..] => {
+                let flags = flags.parse().ok()?;
+                let cas = match cas {
+                    [] => None,
+                    [cas] => Some(cas.parse().ok()?),
+                    _ => return None,
+                };
+                let data = data(&mut rest, bytes.parse().ok()?)?;
+                items.push((key.to_string(), Item { flags, data, cas }));
+            },
+            _ => return None,
+        }
+    }
+}
+
+/* Reads `count` element lines and the line that closes them. */
+fn elements(rest: &mut &[u8], count: usize, shape: Shape) -> Option<Vec<Element>> {
+    let mut elements = Vec::with_capacity(count);
+    for _ in 0..count {
+        elements.push(element(rest, shape)?);
+    }
+    return Some(elements);
+}
+
+fn collection(reply: &[u8], shape: Shape) -> Option<Response> {
+    let (line, mut rest) = first_line(reply);
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let mut collection = Collection::default();
+    let count = match words.as_slice() {
+        ["VALUE", flags, count] => {
+            collection.flags = flags.parse().ok()?;
+            count.parse().ok()?
+        },
+        ["VALUE", position, flags, count, index] => {
+            collection.position = Some(position.parse().ok()?);
+            collection.flags = flags.parse().ok()?;
+            collection.index = Some(index.parse().ok()?);
+            count.parse().ok()?
+        },
+        _ => return None,
+    };
+    collection.elements = elements(&mut rest, count, shape)?;
+    let (status, rest) = first_line(rest);
+    if !rest.is_empty() { return None; }
+    collection.status = status;
+    return Some(Response::Collection(collection));
+}
+
+fn collections(reply: &[u8]) -> Option<Response> {
+    let mut keys = Vec::new();
+    let mut rest = reply;
+    loop {
+        let (line, next) = first_line(rest);
+        rest = next;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["END"] if rest.is_empty() => return Some(Response::Collections(keys)),
+            ["VALUE", key, status] => keys.push((key.to_string(), status.to_string(), None)),
+            ["VALUE", key, status, flags, count] => {
+                let mut found = Vec::new();
+                for _ in 0..count.parse::<usize>().ok()? {
+                    rest = rest.strip_prefix(b"ELEMENT ")?;
+                    found.push(element(&mut rest, Shape::Btree)?);
+                }
+                let collection = Collection {
+                    flags: flags.parse().ok()?, elements: found, status: status.to_string(), ..Default::default()
+                };
+                keys.push((key.to_string(), status.to_string(), Some(collection)));
+            },
+            _ => return None,
+        }
+    }
+}
+
+fn sorted(reply: &[u8]) -> Option<Response> {
+    let (line, mut rest) = first_line(reply);
+    let count = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["ELEMENTS" | "VALUE", count] => count.parse().ok()?,
+        _ => return None,
+    };
+    let elements = elements(&mut rest, count, Shape::Sorted)?;
+    let mut missed = Vec::new();
+    let mut trimmed = Vec::new();
+    loop {
+        let (line, next) = first_line(rest);
+        rest = next;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["MISSED_KEYS", count] => for _ in 0..count.parse::<usize>().ok()? {
+                let (line, next) = first_line(rest);
+                rest = next;
+                let mut words = line.split_whitespace();
+                missed.push((words.next()?.to_string(), words.next().map(str::to_string)));
+            },
+            ["TRIMMED_KEYS", count] => for _ in 0..count.parse::<usize>().ok()? {
+                let (line, next) = first_line(rest);
+                rest = next;
+                let (key, bkey) = line.split_once(' ')?;
+                trimmed.push((key.to_string(), bkey.to_string()));
+            },
+            [status] if rest.is_empty() =>
+                return Some(Response::Sorted { elements, missed, trimmed, status: status.to_string() }),
+            _ => return None,
+        }
+    }
+}
+
+/* "<prefix><name><sep><value>" lines up to END. */
+fn pairs(reply: &[u8], prefix: &str, sep: char) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for line in String::from_utf8_lossy(reply).lines() {
+        if line == "END" { return Some(pairs); }
+        let (name, value) = line.strip_prefix(prefix)?.split_once(sep)?;
+        pairs.push((name.to_string(), value.to_string()));
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(data: &str) -> Element {
+        return Element { data: data.as_bytes().to_vec(), ..Default::default() };
+    }
+
+    fn btree(bkey: &str, eflag: Option<&str>, data: &str) -> Element {
+        return Element { bkey: Some(bkey.to_string()), eflag: eflag.map(str::to_string), ..element(data) };
+    }
+
+    #[test]
+    fn parses_items_with_and_without_cas() {
+        let reply = b"VALUE a 1 8\r\nx\r\nEND\r\n\r\nVALUE b 2 1 42\r\ny\r\nEND\r\n";
+        assert_eq!(parse(b"gets a b", reply), Response::Items(vec![
+            ("a".to_string(), Item { flags: 1, data: b"x\r\nEND\r\n".to_vec(), cas: None }),
+            ("b".to_string(), Item { flags: 2, data: b"y".to_vec(), cas: Some(42) }),
+        ]));
+        assert_eq!(parse(b"get a", b"END\r\n"), Response::Items(Vec::new()));
+    }
+
+    #[test]
+    fn parses_list_and_map_elements() {
+        let list = parse(b"lop get k 0..-1", b"VALUE 3 2\r\n1 x\r\n5 a\r\nbc\r\nEND\r\n");
+        assert_eq!(list, Response::Collection(Collection {
+            flags: 3, elements: vec![element("x"), element("a\r\nbc")], status: "END".to_string(), ..Default::default()
+        }));
+        let map = parse(b"mop get k 1 delete\r\nf\r\n", b"VALUE 0 1\r\nf 2 ab\r\nDELETED\r\n");
+        assert_eq!(map, Response::Collection(Collection {
+            elements: vec![Element { field: Some("f".to_string()), ..element("ab") }],
+            status: "DELETED".to_string(),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn parses_btree_elements_with_and_without_eflag() {
+        let reply = b"VALUE 0 2\r\n1 1 x\r\n0x0a 0xff 2 yz\r\nTRIMMED\r\n";
+        assert_eq!(parse(b"bop get k 0..10", reply), Response::Collection(Collection {
+            elements: vec![btree("1", None, "x"), btree("0x0a", Some("0xff"), "yz")],
+            status: "TRIMMED".to_string(),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn parses_the_position_and_index_of_pwg() {
+        let reply = b"VALUE 7 0 2 1\r\n4 1 a\r\n5 1 b\r\nEND\r\n";
+        assert_eq!(parse(b"bop pwg k 5 1", reply), Response::Collection(Collection {
+            position: Some(7),
+            index: Some(1),
+            elements: vec![btree("4", None, "a"), btree("5", None, "b")],
+            status: "END".to_string(),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn parses_the_status_of_each_mget_key() {
+        let reply = b"VALUE a OK 1 2\r\nELEMENT 1 1 x\r\nELEMENT 2 0x01 1 y\r\nVALUE b NOT_FOUND\r\nEND\r\n";
+        let collection = Collection {
+            flags: 1,
+            elements: vec![btree("1", None, "x"), btree("2", Some("0x01"), "y")],
+            status: "OK".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(parse(b"bop mget 3 2 0..10\r\na b\r\n", reply), Response::Collections(vec![
+            ("a".to_string(), "OK".to_string(), Some(collection)),
+            ("b".to_string(), "NOT_FOUND".to_string(), None),
+        ]));
+    }
+
+    #[test]
+    fn parses_smget_missed_and_trimmed_keys() {
+        let reply = b"ELEMENTS 1\r\na 3 1 0x01 1 x\r\nMISSED_KEYS 2\r\nb\r\nc OUT_OF_RANGE\r\n\
+                      TRIMMED_KEYS 1\r\nd 9\r\nDUPLICATED\r\n";
+        let element = Element { key: Some("a".to_string()), flags: Some(3), ..btree("1", Some("0x01"), "x") };
+        assert_eq!(parse(b"bop smget 7 4 0..10 3\r\na b c d\r\n", reply), Response::Sorted {
+            elements: vec![element],
+            missed: vec![("b".to_string(), None), ("c".to_string(), Some("OUT_OF_RANGE".to_string()))],
+            trimmed: vec![("d".to_string(), "9".to_string())],
+            status: "DUPLICATED".to_string(),
+        });
+    }
+
+    #[test]
+    fn parses_stat_and_attr_pairs() {
+        assert_eq!(parse(b"stats", b"STAT pid 1\r\nSTAT version 1.13.0\r\nEND\r\n"), Response::Stats(vec![
+            ("pid".to_string(), "1".to_string()), ("version".to_string(), "1.13.0".to_string()),
+        ]));
+        assert_eq!(parse(b"getattr k", b"ATTR flags=0\r\nATTR type=b+tree\r\nEND\r\n"), Response::Attributes(vec![
+            ("flags".to_string(), "0".to_string()), ("type".to_string(), "b+tree".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn parses_one_line_replies() {
+        assert_eq!(parse(b"set k 0 0 1\r\nx\r\n", b"STORED\r\n"), Response::Status("STORED".to_string()));
+        assert_eq!(parse(b"incr k 1", b"11\r\n"), Response::Number(None, 11));
+        assert_eq!(parse(b"bop count k 0..10", b"COUNT=4\r\n"), Response::Number(Some("COUNT".to_string()), 4));
+        assert_eq!(parse(b"lop get k 0", b"CLIENT_ERROR bad command line format\r\n"),
+                   Response::Error("CLIENT_ERROR bad command line format".to_string()));
+        assert_eq!(parse(b"lop get k 0", b"NOT_FOUND\r\n"), Response::Status("NOT_FOUND".to_string()));
+    }
+
+    #[test]
+    fn keeps_replies_that_do_not_parse_as_lines() {
+        assert_eq!(parse(b"bop get k 0..10", b"VALUE 0 1\r\n1 9 x\r\nEND\r\n"),
+                   Response::Lines(vec!["VALUE 0 1".to_string(), "1 9 x".to_string(), "END".to_string()]));
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, IsTerminal};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::connect::cluster::Cluster;
+use crate::connect::reply;
+use crate::output;
+
+const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// What `\stats` shows, and how often it polls.
+struct View {
+    /// The request that fetches the numbers.
+    req: String,
+    /// Seconds between samples; `None` takes one sample.
+    watch: Option<Duration>,
+    /// Samples to take while watching; `None` goes on until Enter is pressed.
+    count: Option<u64>,
+}
+
+impl View {
+    /* `[settings|items|slabs|prefix|prefixes|zookeeper] [--watch <secs> [--count <n>]]` */
+    fn parse(args: &[&str]) -> Result<View, String> {
+        let mut view = View { req: "stats".to_string(), watch: None, count: None };
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                                             .and_then(|v| v.parse::<f64>().ok())
+                                             .filter(|v| *v > 0.0)
+                                             .ok_or_else(|| format!("{} takes a positive number", name));
+            match arg {
+                "settings" | "items" | "slabs" | "prefixes" | "zookeeper" => view.req = format!("stats {}", arg),
+                /* Per-prefix hits are only in the detail dump, and only once "stats detail on" was given. */
+                "prefix" => view.req = "stats detail dump".to_string(),
+                "--watch" => view.watch = Some(Duration::from_secs_f64(value("--watch")?)),
+                "--count" => view.count = Some(value("--count")?.ceil() as u64),
+                _ => return Err(format!("unexpected \"{}\", expected settings | items | slabs | prefix | prefixes | zookeeper | --watch | --count", arg)),
+            }
+        }
+        if view.count.is_some() && view.watch.is_none() {
+            return Err("--count needs --watch".to_string());
+        }
+        return Ok(view);
+    }
+}
+
+/// One poll of one node.
+struct Sample {
+    at: Instant,
+    values: Vec<(String, String)>,
+}
+
+impl Sample {
+    fn get(&self, name: &str) -> Option<f64> {
+        self.values.iter().find(|(n, _)| n == name).and_then(|(_, v)| v.parse().ok())
+    }
+
+    /* How much `name` went up since `prev`, and by how much a second. */
+    fn delta(&self, prev: &Sample, name: &str) -> Option<(f64, f64)> {
+        let delta = self.get(name)? - prev.get(name)?;
+        let secs = self.at.duration_since(prev.at).as_secs_f64().max(1e-3);
+        return Some((delta, delta / secs));
+    }
+}
+
+/* STAT lines as name/value pairs. PREFIX lines ("PREFIX <name> <field> <value> ...")
+   become "<name>:<field>" pairs, like the per-slab stats. */
+fn pairs(reply: &[u8]) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    for line in String::from_utf8_lossy(reply).lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["STAT", name, value @ ..] => pairs.push((name.to_string(), value.join(" "))),
+            ["PREFIX", prefix, fields @ ..] => for field in fields.chunks(2) {
+                if let [name, value] = field {
+                    pairs.push((format!("{}:{}", prefix, name), value.to_string()));
+                }
+            },
+            _ => (),
+        }
+    }
+    return pairs;
+}
+
+/* Section of the general stats a name belongs in. */
+fn group(name: &str) -> &'static str {
+    match name {
+        "pid" | "uptime" | "time" | "version" | "libevent" | "pointer_size" | "threads" => "server",
+        _ if name.starts_with("rusage_") => "server",
+        _ if name.contains("conn") => "connections",
+        "bytes_read" | "bytes_written" => "network",
+        "bytes" | "limit_maxbytes" | "engine_maxbytes" | "evictions" | "reclaimed" | "outofmemorys" => "memory",
+        _ if name.ends_with("_items") || name.starts_with("sticky_") => "items",
+        _ if name.ends_with("_hits") || name.ends_with("_misses") => "hits",
+        _ if name.starts_with("cmd_") => "commands",
+        _ => "other",
+    }
+}
+
+const GROUPS: [&str; 8] = ["server", "connections", "memory", "items", "commands", "hits", "network", "other"];
+
+fn percent(part: f64, whole: f64) -> String {
+    if whole > 0.0 { format!("{:.1}%", part * 100.0 / whole) } else { "-".to_string() }
+}
+
+/* Hit ratio, memory use and eviction rate, from whichever stats the node reports. */
+fn derived(cur: &Sample, prev: Option<&Sample>) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    if let (Some(hits), Some(misses)) = (cur.get("get_hits"), cur.get("get_misses")) {
+        let mut line = format!("{} ({} hits, {} misses)", percent(hits, hits + misses), hits, misses);
+        if let Some((Some((hits, _)), Some((misses, _)))) = prev.map(|p| (cur.delta(p, "get_hits"), cur.delta(p, "get_misses"))) {
+            if hits + misses > 0.0 {
+                line += &format!(", {} since the last sample", percent(hits, hits + misses));
+            }
+        }
+        out.push(("hit ratio".to_string(), line));
+    }
+    if let (Some(bytes), Some(limit)) = (cur.get("bytes"), cur.get("limit_maxbytes").or(cur.get("engine_maxbytes"))) {
+        out.push(("memory usage".to_string(), format!("{} ({} of {} bytes)", percent(bytes, limit), bytes, limit)));
+    }
+    match (prev, cur.get("evictions"), cur.get("uptime")) {
+        (Some(prev), _, _) => if let Some((_, rate)) = cur.delta(prev, "evictions") {
+            out.push(("evictions/s".to_string(), format!("{:.2}", rate)));
+        },
+        (None, Some(evictions), Some(uptime)) if uptime > 0.0 =>
+            out.push(("evictions/s".to_string(), format!("{:.2} (average since start)", evictions / uptime))),
+        _ => (),
+    }
+    if let Some(prev) = prev {
+        for (name, stat) in [("gets/s", "cmd_get"), ("sets/s", "cmd_set")] {
+            if let Some((_, rate)) = cur.delta(prev, stat) {
+                out.push((name.to_string(), format!("{:.1}", rate)));
+            }
+        }
+    }
+    return out;
+}
+
+/* Rows of `table`, highlighted where `changed` says so. */
+fn highlight(table: String, changed: &[bool], color: bool) -> String {
+    if !color { return table; }
+    let mut lines = table.lines();
+    let mut out = lines.next().map_or(String::new(), |head| format!("{}\n", head));
+    for (i, line) in lines.enumerate() {
+        out += &match changed.get(i).copied().unwrap_or(false) {
+            true => format!("{}{}{}\n", HIGHLIGHT, line, RESET),
+            false => format!("{}\n", line),
+        };
+    }
+    return out;
+}
+
+/* Plain stats as name/value lists by section; with an earlier sample, also what changed since. */
+fn flat(cur: &Sample, prev: Option<&Sample>, color: bool) -> String {
+    let mut out = String::new();
+    let derived = derived(cur, prev);
+    if !derived.is_empty() {
+        let rows: Vec<Vec<String>> = derived.into_iter().map(|(name, value)| vec![name, value]).collect();
+        out += &output::table(&["derived", ""], &rows);
+    }
+    /* Only the general stats have sections; settings and the like are one list. */
+    let general = cur.values.iter().any(|(name, _)| group(name) != "other");
+    let sections: &[&str] = if general { &GROUPS } else { &["stat"] };
+    for &section in sections {
+        let mut names: Vec<&(String, String)> = cur.values.iter()
+                                                   .filter(|(name, _)| !general || group(name) == section)
+                                                   .collect();
+        if names.is_empty() { continue; }
+        names.sort();
+        let mut changed = Vec::new();
+        let rows: Vec<Vec<String>> = names.iter().map(|(name, value)| {
+            let mut row = vec![name.clone(), value.clone()];
+            if let Some(prev) = prev {
+                let (delta, rate) = cur.delta(prev, name).unwrap_or((0.0, 0.0));
+                changed.push(delta != 0.0);
+                row.push(if delta != 0.0 { format!("{:+}", delta) } else { String::new() });
+                row.push(if delta != 0.0 { format!("{:.1}/s", rate) } else { String::new() });
+            }
+            row
+        }).collect();
+        let head: Vec<&str> = if prev.is_some() { vec![section, "value", "delta", "rate"] } else { vec![section, "value"] };
+        out += &highlight(output::table(&head, &rows), &changed, color);
+    }
+    return out;
+}
+
+/* Stats named "<row>:<column>" (per slab, per prefix) as one table, and the rest as a list. */
+fn tabled(cur: &Sample, prev: Option<&Sample>, color: bool) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+    let mut rows: BTreeMap<(u64, &str), BTreeMap<&str, &str>> = BTreeMap::new();
+    let mut rest = Vec::new();
+    for (name, value) in cur.values.iter() {
+        match name.trim_start_matches("items:").rsplit_once(':') {
+            Some((row, column)) => {
+                if !columns.contains(&column) { columns.push(column); }
+                /* Slab ids sort as numbers, prefixes by name. */
+                rows.entry((row.parse().unwrap_or(u64::MAX), row)).or_default().insert(column, value);
+            },
+            None => rest.push((name.clone(), value.clone())),
+        }
+    }
+    let mut out = String::new();
+    if !rows.is_empty() {
+        let hits = columns.contains(&"get") && columns.contains(&"hit");
+        let mut head = vec![""];
+        head.extend(columns.iter());
+        if hits { head.push("hit%"); }
+        let mut rows: Vec<(&str, BTreeMap<&str, &str>)> = rows.into_iter().map(|((_, row), cells)| (row, cells)).collect();
+        if hits {
+            let gets = |cells: &BTreeMap<&str, &str>| cells.get("get").and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+            rows.sort_by_key(|(_, cells)| std::cmp::Reverse(gets(cells)));
+        }
+        let mut changed = Vec::new();
+        let table: Vec<Vec<String>> = rows.iter().map(|(row, cells)| {
+            let mut line = vec![row.to_string()];
+            line.extend(columns.iter().map(|c| cells.get(c).map_or(String::new(), |v| v.to_string())));
+            if hits {
+                let number = |c: &str| cells.get(c).and_then(|v| v.parse::<f64>().ok()).unwrap_or(0.0);
+                line.push(percent(number("hit"), number("get")));
+            }
+            changed.push(prev.is_some_and(|prev| columns.iter().any(|c| {
+                let name = cur.values.iter().map(|(n, _)| n).find(|n| n.trim_start_matches("items:") == format!("{}:{}", row, c));
+                name.and_then(|n| cur.delta(prev, n)).is_some_and(|(d, _)| d != 0.0)
+            })));
+            line
+        }).collect();
+        out += &highlight(output::table(&head, &table), &changed, color);
+    }
+    if !rest.is_empty() {
+        out += &flat(&Sample { at: cur.at, values: rest }, prev, color);
+    }
+    return out;
+}
+
+fn render(cur: &Sample, prev: Option<&Sample>, color: bool) -> String {
+    if cur.values.iter().any(|(name, _)| name.contains(':')) {
+        return tabled(cur, prev, color);
+    }
+    return flat(cur, prev, color);
+}
+
+/* A flag set once Enter is pressed, and the thread waiting for it. */
+type Enter = (Arc<AtomicBool>, JoinHandle<()>);
+
+/* Waits for Enter, for watches without a sample count. */
+fn on_enter() -> Enter {
+    let pressed = Arc::new(AtomicBool::new(false));
+    let flag = pressed.clone();
+    let reader = thread::spawn(move || {
+        let _ = io::stdin().lock().read_line(&mut String::new());
+        flag.store(true, Ordering::Relaxed);
+    });
+    return (pressed, reader);
+}
+
+/// Runs `\stats [<group>] [--watch <secs> [--count <n>]]` against every node.
+/// An error reply ends the run, except once a watch only Enter can end is
+/// under way; there it shows in place of the node's numbers.
+pub fn run(cluster: &mut Cluster, args: &[&str]) -> Result<(), String> {
+    let view = View::parse(args)?;
+    let tty = io::stdout().is_terminal();
+    /* Only a terminal can press Enter; anywhere else the watch would never end. */
+    let endless = match (view.watch, view.count) {
+        (Some(_), None) if io::stdin().is_terminal() => true,
+        (Some(_), None) => return Err("--watch needs --count when stdin is not a terminal".to_string()),
+        _ => false,
+    };
+    /* Started after the first sample; from then on only Enter ends the watch. */
+    let mut stop: Option<Enter> = None;
+    let pressed = |stop: &Option<Enter>| stop.as_ref().is_some_and(|(flag, _)| flag.load(Ordering::Relaxed));
+    let mut prev: BTreeMap<String, Sample> = BTreeMap::new();
+    for taken in 0.. {
+        if view.count.is_some_and(|count| taken >= count) { break; }
+        if taken > 0 {
+            let until = Instant::now() + view.watch.unwrap_or_default();
+            while Instant::now() < until && !pressed(&stop) {
+                thread::sleep(Duration::from_millis(50).min(until.saturating_duration_since(Instant::now())));
+            }
+            if pressed(&stop) { break; }
+        }
+
+        let replies = cluster.write(view.req.clone().into_bytes());
+        let mut screen = String::new();
+        if let Some(every) = view.watch {
+            if tty { screen += "\x1b[H\x1b[2J"; }
+            screen += &format!("{} every {:?}, sample {}{}\n", view.req, every, taken + 1,
+                               if endless { " (Enter stops)" } else { "" });
+        }
+        for (node, reply) in replies {
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(err) => { eprintln!("ERROR: {}", err); continue }
+            };
+            if reply::is_error(&reply) {
+                let err = String::from_utf8_lossy(&reply).trim_end().to_string();
+                if stop.is_none() { return Err(err); }
+                screen += &format!("ERROR: {}\n", err);
+                continue;
+            }
+            let sample = Sample { at: Instant::now(), values: pairs(&reply) };
+            if cluster.len() > 1 { screen += &format!("[{}]\n", node); }
+            if sample.values.is_empty() {
+                screen += "(nothing to show";
+                screen += if view.req == "stats detail dump" { "; turn prefix details on with \"stats detail on\")\n" } else { ")\n" };
+            }
+            screen += &render(&sample, prev.get(&node), tty);
+            prev.insert(node, sample);
+        }
+        print!("{}", screen);
+        if view.watch.is_none() { break; }
+        if endless && stop.is_none() { stop = Some(on_enter()); }
+    }
+    if let Some((_, reader)) = stop {
+        let _ = reader.join();
+    }
+    return Ok(());
+}
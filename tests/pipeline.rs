@@ -0,0 +1,77 @@
+//! `StreamClient::pipeline` against a stand-in that answers each request as it reads it.
+
+#![allow(clippy::needless_return)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use arcus_cli::connect::stream::Timeouts;
+use arcus_cli::connect::tcp::TcpClient;
+
+/* Answers up to `limit` request lines with `reply`, then hangs up. */
+fn stand_in(reply: Vec<u8>, limit: usize) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(conn.try_clone().unwrap());
+        let mut line = String::new();
+        for _ in 0..limit {
+            if reader.read_line(&mut line).unwrap_or(0) == 0 { return; }
+            if conn.write_all(&reply).is_err() { return; }
+            line.clear();
+        }
+    });
+    return addr;
+}
+
+fn client() -> TcpClient {
+    let mut client = TcpClient::default();
+    let limit = Some(Duration::from_secs(5));
+    client.timeouts = Timeouts { connect: limit, read: limit, write: limit };
+    return client;
+}
+
+#[test]
+fn reads_replies_while_writing() {
+    /* Far more requests and replies than the socket buffers hold. */
+    let mut reply = format!("VALUE k 0 {}\r\n", 16 * 1024).into_bytes();
+    reply.extend(vec![b'x'; 16 * 1024]);
+    reply.extend_from_slice(b"\r\nEND\r\n");
+    let addr = stand_in(reply.clone(), usize::MAX);
+    let keys = vec!["k".repeat(250); 40];
+    let reqs = vec![format!("get {}\r\n", keys.join(" ")).into_bytes(); 1000];
+
+    let res = client().pipeline(&addr, &reqs);
+    assert_eq!(res.len(), reqs.len());
+    let failed = res.iter().filter(|res| !matches!(res, Ok(read) if *read == reply)).count();
+    assert_eq!(failed, 0);
+}
+
+#[test]
+fn reports_lost_and_unsent_requests() {
+    let addr = stand_in(b"STORED\r\n".to_vec(), 1);
+    let mut reqs = vec![b"set k 0 0 1\r\nx\r\n".to_vec(); 3];
+    reqs.push(vec![b'k'; 128 * 1024]);
+    reqs.push(b"get k\r\n".to_vec());
+
+    let res = client().pipeline(&addr, &reqs);
+    assert_eq!(res[0].as_ref().unwrap(), b"STORED\r\n");
+    let closed = res[1].as_ref().unwrap_err().to_string();
+    assert_eq!(closed, "connection closed by server");
+    let lost = res[2].as_ref().unwrap_err().to_string();
+    assert!(lost.contains("\"set k 0 0 1\"; it may or may not have been applied"), "{}", lost);
+    for unsent in &res[3..] {
+        assert_eq!(unsent.as_ref().unwrap_err().to_string(), format!("not connected to {}", addr));
+    }
+}
+
+#[test]
+fn pipelines_over_one_connection() {
+    let addr = stand_in(b"END\r\n".to_vec(), usize::MAX);
+    let mut client = client();
+    let reqs = vec![b"get a\r\n".to_vec(), b"get b\r\n".to_vec()];
+    assert!(client.pipeline(&addr, &reqs).into_iter().all(|res| res.unwrap() == b"END\r\n"));
+    assert!(client.pipeline(&addr, &reqs).into_iter().all(|res| res.unwrap() == b"END\r\n"));
+}
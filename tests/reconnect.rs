@@ -0,0 +1,157 @@
+//! What `StreamClient` does when a connection drops, against a scripted stand-in.
+
+#![allow(clippy::needless_return)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use arcus_cli::connect::credentials::Credentials;
+use arcus_cli::connect::event::Event;
+use arcus_cli::connect::reply::Expect;
+use arcus_cli::connect::stream::Reconnect;
+use arcus_cli::connect::tcp::TcpClient;
+
+type Script = Box<dyn FnOnce(&mut BufReader<TcpStream>, &mut TcpStream) + Send>;
+
+/* Runs one script per connection on `listener`, in order. */
+fn serve(listener: TcpListener, scripts: Vec<Script>) {
+    thread::spawn(move || {
+        for script in scripts {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(conn.try_clone().unwrap());
+            script(&mut reader, &mut conn);
+        }
+    });
+}
+
+fn stand_in(scripts: Vec<Script>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    serve(listener, scripts);
+    return addr;
+}
+
+fn line(reader: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    return line;
+}
+
+/* A client that keeps the events it was told about. */
+fn client(retry: Reconnect) -> (TcpClient, Arc<Mutex<Vec<String>>>) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = events.clone();
+    let mut client = TcpClient::default();
+    client.retry = retry;
+    client.log = Some(Arc::new(move |event: &Event| seen.lock().unwrap().push(event.to_string())));
+    return (client, events);
+}
+
+#[test]
+fn replays_reads_whose_reply_was_lost() {
+    let addr = stand_in(vec![
+        Box::new(|reader, conn| {
+            assert_eq!(line(reader), "get k\r\n");
+            conn.write_all(b"VALUE k 0 5\r\nab").unwrap();
+        }),
+        Box::new(|reader, conn| {
+            assert_eq!(line(reader), "get k\r\n");
+            conn.write_all(b"VALUE k 0 5\r\nabcde\r\nEND\r\n").unwrap();
+        }),
+    ]);
+    let (mut client, events) = client(Reconnect::default());
+    let reply = client.request(&addr, b"get k\r\n", Expect::Values).unwrap();
+    assert_eq!(reply, b"VALUE k 0 5\r\nabcde\r\nEND\r\n");
+    assert_eq!(*events.lock().unwrap(), ["Replaying \"get k\""]);
+}
+
+#[test]
+fn reports_writes_whose_reply_was_lost() {
+    let (sent, applied) = mpsc::channel();
+    let addr = stand_in(vec![
+        Box::new(move |reader, _| {
+            sent.send(line(reader) + &line(reader)).unwrap();
+        }),
+    ]);
+    let (mut client, events) = client(Reconnect::default());
+    let err = client.request(&addr, b"set k 0 0 1\r\nx\r\n", Expect::Status).unwrap_err();
+    assert!(err.to_string().contains("\"set k 0 0 1\""), "{}", err);
+    assert!(err.to_string().contains("may or may not have been applied"), "{}", err);
+    assert_eq!(applied.recv().unwrap(), "set k 0 0 1\r\nx\r\n");
+    assert!(events.lock().unwrap().is_empty());
+    assert!(!client.is_connected());
+}
+
+#[test]
+fn retries_refused_connections_with_backoff() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    drop(listener);
+    let late = addr.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        let listener = TcpListener::bind(&late).unwrap();
+        serve(listener, vec![Box::new(|reader, conn| {
+            assert_eq!(line(reader), "version\r\n");
+            conn.write_all(b"VERSION 1.13.0\r\n").unwrap();
+        })]);
+    });
+    let (mut client, events) = client(Reconnect { attempts: 10, backoff: Duration::from_millis(40) });
+    assert_eq!(client.request(&addr, b"version\r\n", Expect::Status).unwrap(), b"VERSION 1.13.0\r\n");
+
+    let events = events.lock().unwrap();
+    let (reconnected, retrying) = events.split_last().unwrap();
+    assert_eq!(reconnected, &format!("Reconnected to {}", addr));
+    assert!(retrying.len() >= 2, "{:?}", events);
+    assert!(retrying[0].ends_with("(retrying in 40ms)"), "{:?}", events);
+    assert!(retrying[1].ends_with("(retrying in 80ms)"), "{:?}", events);
+}
+
+#[test]
+fn gives_up_after_the_last_attempt() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    drop(listener);
+    let (mut client, events) = client(Reconnect { attempts: 2, backoff: Duration::from_millis(10) });
+    let err = client.connect(&addr).unwrap_err();
+    assert_eq!(err.exit_code(), arcus_cli::connect::error::EXIT_REFUSED);
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2, "{:?}", events);
+    assert!(events[1].ends_with("(retrying in 20ms)"), "{:?}", events);
+}
+
+/* Answers the SASL exchange for "user"/"secret" with PLAIN. */
+fn sign_in(reader: &mut BufReader<TcpStream>, conn: &mut TcpStream) {
+    assert_eq!(line(reader), "sasl mech\r\n");
+    conn.write_all(b"SASL_MECH PLAIN\r\n").unwrap();
+    assert_eq!(line(reader), "sasl auth PLAIN 12\r\n");
+    let mut data = [0; 14];
+    reader.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"\0user\0secret\r\n");
+    conn.write_all(b"SASL_OK\r\n").unwrap();
+}
+
+#[test]
+fn signs_in_again_after_reconnecting() {
+    let addr = stand_in(vec![
+        Box::new(|reader, conn| {
+            sign_in(reader, conn);
+            assert_eq!(line(reader), "get k\r\n");
+        }),
+        Box::new(|reader, conn| {
+            sign_in(reader, conn);
+            assert_eq!(line(reader), "get k\r\n");
+            conn.write_all(b"END\r\n").unwrap();
+        }),
+    ]);
+    let (mut client, events) = client(Reconnect::default());
+    client.auth = Some(Credentials {
+        username: "user".to_string(), password: "secret".to_string(), mech: Some("PLAIN".to_string()), ..Default::default()
+    });
+    assert_eq!(client.request(&addr, b"get k\r\n", Expect::Values).unwrap(), b"END\r\n");
+    assert_eq!(*events.lock().unwrap(), [
+        "SASL: authenticated as user with PLAIN", "SASL: authenticated as user with PLAIN", "Replaying \"get k\"",
+    ]);
+}
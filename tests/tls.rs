@@ -0,0 +1,120 @@
+//! TLS sessions against a local rustls server with a self-signed certificate.
+
+#![allow(clippy::needless_return)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use arcus_cli::connect::tls::{self, TlsStream};
+
+/// A self-signed certificate for "localhost" and the PEM file holding it.
+struct Identity {
+    cert: CertificateDer<'static>,
+    key: PrivateKeyDer<'static>,
+    pem: PathBuf,
+}
+
+fn identity(name: &str) -> Identity {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let pem = std::env::temp_dir().join(format!("arcus-cli-{}-{}.pem", name, std::process::id()));
+    fs::write(&pem, certified.cert.pem()).unwrap();
+    return Identity {
+        cert: certified.cert.der().clone(),
+        key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der())),
+        pem,
+    };
+}
+
+/* Serves one session that answers "version" and reports the SNI the client sent. */
+fn server(identity: &Identity) -> (String, Receiver<Option<String>>) {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+                              .with_safe_default_protocol_versions().unwrap()
+                              .with_no_client_auth()
+                              .with_single_cert(vec![identity.cert.clone()], identity.key.clone_key())
+                              .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let (sni, seen) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut conn = ServerConnection::new(Arc::new(config)).unwrap();
+        while conn.is_handshaking() {
+            if conn.complete_io(&mut sock).is_err() { return; }
+        }
+        sni.send(conn.server_name().map(str::to_string)).unwrap();
+        let mut stream = BufReader::new(StreamOwned::new(conn, sock));
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        assert_eq!(line, "version\r\n");
+        stream.get_mut().write_all(b"VERSION 1.8.0\r\n").unwrap();
+        stream.get_mut().flush().unwrap();
+    });
+    return (addr, seen);
+}
+
+fn version(mut stream: TlsStream) -> String {
+    stream.write_all(b"version\r\n").unwrap();
+    let mut reply = [0; 15];
+    stream.read_exact(&mut reply).unwrap();
+    return String::from_utf8_lossy(&reply).to_string();
+}
+
+#[test]
+fn verifies_with_the_given_ca() {
+    let identity = identity("ca");
+    let (addr, seen) = server(&identity);
+    let config = tls::config(Some(&identity.pem), None, None, false).unwrap();
+    let stream = TlsStream::connect(config, "localhost", TcpStream::connect(&addr).unwrap()).unwrap();
+    assert_eq!(version(stream), "VERSION 1.8.0\r\n");
+    assert_eq!(seen.recv().unwrap().as_deref(), Some("localhost"));
+    fs::remove_file(&identity.pem).unwrap();
+}
+
+#[test]
+fn rejects_unknown_issuers() {
+    let identity = identity("roots");
+    let (addr, _) = server(&identity);
+    let config = tls::config(None, None, None, false).unwrap();
+    let err = TlsStream::connect(config, "localhost", TcpStream::connect(&addr).unwrap()).err().unwrap();
+    assert!(err.to_string().contains("UnknownIssuer"), "{}", err);
+    fs::remove_file(&identity.pem).unwrap();
+}
+
+#[test]
+fn rejects_other_names() {
+    let identity = identity("name");
+    let (addr, _) = server(&identity);
+    let config = tls::config(Some(&identity.pem), None, None, false).unwrap();
+    let err = TlsStream::connect(config, "cache.example.com", TcpStream::connect(&addr).unwrap()).err().unwrap();
+    assert!(err.to_string().contains("not valid for name"), "{}", err);
+    fs::remove_file(&identity.pem).unwrap();
+}
+
+#[test]
+fn insecure_skips_verification() {
+    let identity = identity("insecure");
+    let (addr, seen) = server(&identity);
+    let config = tls::config(None, None, None, true).unwrap();
+    let stream = TlsStream::connect(config, "cache.example.com", TcpStream::connect(&addr).unwrap()).unwrap();
+    assert_eq!(version(stream), "VERSION 1.8.0\r\n");
+    assert_eq!(seen.recv().unwrap().as_deref(), Some("cache.example.com"));
+    fs::remove_file(&identity.pem).unwrap();
+}
+
+#[test]
+fn sends_no_sni_for_addresses() {
+    let identity = identity("address");
+    let (addr, seen) = server(&identity);
+    let config = tls::config(None, None, None, true).unwrap();
+    let stream = TlsStream::connect(config, "[127.0.0.1]", TcpStream::connect(&addr).unwrap()).unwrap();
+    assert_eq!(version(stream), "VERSION 1.8.0\r\n");
+    assert_eq!(seen.recv().unwrap(), None);
+    fs::remove_file(&identity.pem).unwrap();
+}
@@ -0,0 +1,174 @@
+//! `cache_list` against a scripted ZooKeeper stand-in on a local port.
+
+#![allow(clippy::needless_return)]
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use arcus_cli::connect::error::ConnectError;
+use arcus_cli::connect::zookeeper::cache_list;
+
+const ERR_NO_NODE: i32 = -101;
+const ERR_CONNECTION_LOSS: i32 = -4;
+
+/* Runs `script` on the first connection to a fresh local port. */
+fn stand_in(script: impl FnOnce(TcpStream) + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let (conn, _) = listener.accept().unwrap();
+        script(conn);
+    });
+    return addr;
+}
+
+fn read_packet(conn: &mut TcpStream) -> Vec<u8> {
+    let mut len = [0; 4];
+    conn.read_exact(&mut len).unwrap();
+    let mut packet = vec![0; i32::from_be_bytes(len) as usize];
+    conn.read_exact(&mut packet).unwrap();
+    return packet;
+}
+
+fn write_packet(conn: &mut TcpStream, packet: &[u8]) {
+    conn.write_all(&(packet.len() as i32).to_be_bytes()).unwrap();
+    conn.write_all(packet).unwrap();
+}
+
+fn handshake(conn: &mut TcpStream) {
+    let req = read_packet(conn);
+    assert_eq!(req.len(), 44);
+    let mut resp = Vec::new();
+    resp.extend_from_slice(&0i32.to_be_bytes());        /* protocolVersion */
+    resp.extend_from_slice(&10000i32.to_be_bytes());    /* timeOut */
+    resp.extend_from_slice(&1i64.to_be_bytes());        /* sessionId */
+    resp.extend_from_slice(&16i32.to_be_bytes());       /* passwd */
+    resp.extend_from_slice(&[0; 16]);
+    write_packet(conn, &resp);
+}
+
+/* Reads a getChildren request for `path` and answers it with `err` and `children`. */
+fn children(conn: &mut TcpStream, path: &str, err: i32, children: &[&str]) {
+    let req = read_packet(conn);
+    assert_eq!(&req[4..8], &8i32.to_be_bytes());
+    assert_eq!(&req[8..12], &(path.len() as i32).to_be_bytes());
+    assert_eq!(&req[12..12 + path.len()], path.as_bytes());
+    let mut resp = req[..4].to_vec();                   /* xid */
+    resp.extend_from_slice(&7i64.to_be_bytes());        /* zxid */
+    resp.extend_from_slice(&err.to_be_bytes());
+    if err == 0 {
+        resp.extend_from_slice(&(children.len() as i32).to_be_bytes());
+        for child in children {
+            resp.extend_from_slice(&(child.len() as i32).to_be_bytes());
+            resp.extend_from_slice(child.as_bytes());
+        }
+    }
+    write_packet(conn, &resp);
+}
+
+fn closed_port() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    return listener.local_addr().unwrap().to_string();
+}
+
+#[test]
+fn lists_cache_nodes() {
+    let addr = stand_in(|mut conn| {
+        handshake(&mut conn);
+        children(&mut conn, "/arcus/cache_list/svc", 0,
+                 &["10.0.0.1:11211-cache1", "10.0.0.2:11212-cache-2", "10.0.0.3:11213"]);
+    });
+    let nodes = cache_list(&addr, "svc").unwrap();
+    assert_eq!(nodes, ["10.0.0.1:11211", "10.0.0.2:11212", "10.0.0.3:11213"]);
+}
+
+#[test]
+fn lists_no_nodes() {
+    let addr = stand_in(|mut conn| {
+        handshake(&mut conn);
+        children(&mut conn, "/arcus/cache_list/svc", 0, &[]);
+    });
+    assert!(cache_list(&addr, "svc").unwrap().is_empty());
+}
+
+#[test]
+fn skips_unreachable_servers() {
+    let addr = stand_in(|mut conn| {
+        handshake(&mut conn);
+        children(&mut conn, "/arcus/cache_list/svc", 0, &["10.0.0.1:11211-cache1"]);
+    });
+    let ensemble = format!("{},{}", closed_port(), addr);
+    assert_eq!(cache_list(&ensemble, "svc").unwrap(), ["10.0.0.1:11211"]);
+}
+
+#[test]
+fn reports_unknown_service_codes() {
+    let addr = stand_in(|mut conn| {
+        handshake(&mut conn);
+        children(&mut conn, "/arcus/cache_list/nosuch", ERR_NO_NODE, &[]);
+    });
+    let err = cache_list(&addr, "nosuch").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert_eq!(err.to_string(), "no such znode: /arcus/cache_list/nosuch");
+}
+
+#[test]
+fn reports_server_errors() {
+    let addr = stand_in(|mut conn| {
+        handshake(&mut conn);
+        children(&mut conn, "/arcus/cache_list/svc", ERR_CONNECTION_LOSS, &[]);
+    });
+    let err = cache_list(&addr, "svc").unwrap_err();
+    assert_eq!(err.to_string(), "ZooKeeper error -4 on /arcus/cache_list/svc");
+}
+
+#[test]
+fn reports_unreachable_ensembles() {
+    let addr = closed_port();
+    let err = cache_list(&addr, "svc").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    assert!(err.to_string().starts_with(&addr));
+    assert_eq!(cache_list("", "svc").unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn reports_unresolved_servers() {
+    let ensemble = format!("{},localhost:nosuchport", closed_port());
+    let err = cache_list(&ensemble, "svc").unwrap_err();
+    assert!(err.to_string().starts_with("localhost:nosuchport: "), "{}", err);
+    let inner = err.get_ref().and_then(|inner| inner.downcast_ref::<ConnectError>());
+    assert!(matches!(inner, Some(ConnectError::Resolve(..))), "{:?}", inner);
+}
+
+#[test]
+fn rejects_short_handshakes() {
+    let addr = stand_in(|mut conn| {
+        read_packet(&mut conn);
+        write_packet(&mut conn, &[0; 8]);
+    });
+    let err = cache_list(&addr, "svc").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_oversized_replies() {
+    let addr = stand_in(|mut conn| {
+        handshake(&mut conn);
+        read_packet(&mut conn);
+        conn.write_all(&i32::MAX.to_be_bytes()).unwrap();
+    });
+    let err = cache_list(&addr, "svc").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_truncated_replies() {
+    let addr = stand_in(|mut conn| {
+        handshake(&mut conn);
+        read_packet(&mut conn);
+        conn.write_all(&100i32.to_be_bytes()).unwrap();
+        conn.write_all(&[0; 10]).unwrap();
+    });
+    let err = cache_list(&addr, "svc").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}